@@ -0,0 +1,145 @@
+// Runs games without a window, for trying out rules and bots in batches,
+// e.g. `cargo run --no-default-features --bin rustycubes-sim -- --seed 42 --bot
+// --pieces 500 --format json`, which builds without ggez
+use rustycubes::generator::GeneratorKind;
+use rustycubes::replay::{self, Replay};
+use rustycubes::rules::{Mode, SWITCH_FLAGS, VALUE_FLAGS};
+use rustycubes::sim::{self, Player};
+
+const USAGE: &str = "usage: rustycubes-sim (--bot | --script <file> | --replay <file>) [--pieces <number>] [--format text|json] [--mode marathon|classic|master] [--generator bag|uniform|history] [--seed <number>] [--width <4-40>] [--height <4-80>] [--hidden-rows <0-40>] [--preview <1-6>] [--gravity guideline|nes|<cells per frame,...>] [--lines-per-level <number>] [--lock-delay <frames>] [--lock-resets <number>] [--das <frames>] [--arr <frames>] [--no-das-carry] [--all-spin]";
+
+enum Format {
+    Text,
+    Json,
+}
+
+struct Options {
+    player: Player,
+    // The script or replay to play. A script is just inputs, as the frames
+    // since the last one and +action or -action.
+    path: String,
+    pieces: Option<usize>,
+    format: Format,
+    mode: Mode,
+    generator: Option<GeneratorKind>,
+    seed: u64,
+    rule_flags: Vec<(String, String)>,
+}
+
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let mut player = None;
+        // Flags that set up a new game, which a replay already has
+        let mut game_flags = Vec::new();
+        let mut options = Options {
+            player: Player::Bot,
+            path: String::new(),
+            pieces: None,
+            format: Format::Text,
+            mode: Mode::default(),
+            generator: None,
+            seed: rand::random(),
+            rule_flags: Vec::new(),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if arg == "--mode" || arg == "--generator" || arg == "--seed"
+                || VALUE_FLAGS.contains(&arg.as_str()) || SWITCH_FLAGS.contains(&arg.as_str())
+            {
+                game_flags.push(arg.clone());
+            }
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--bot" => player = Some(Player::Bot),
+                "--script" => {
+                    options.path = value()?;
+                    player = Some(Player::Script);
+                },
+                "--replay" => {
+                    options.path = value()?;
+                    player = Some(Player::Replay);
+                },
+                "--pieces" => {
+                    let pieces = value()?;
+                    options.pieces = Some(pieces.parse().map_err(|_| format!("invalid piece count '{}'", pieces))?);
+                },
+                "--format" => {
+                    options.format = match value()?.as_str() {
+                        "text" => Format::Text,
+                        "json" => Format::Json,
+                        format => return Err(format!("unknown format '{}', expected text or json", format)),
+                    };
+                },
+                "--mode" => options.mode = value()?.parse()?,
+                "--generator" => options.generator = Some(value()?.parse()?),
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = seed.parse().map_err(|_| format!("invalid seed '{}'", seed))?;
+                },
+                flag if VALUE_FLAGS.contains(&flag) => {
+                    let value = value()?;
+                    options.rule_flags.push((arg, value));
+                },
+                flag if SWITCH_FLAGS.contains(&flag) => options.rule_flags.push((arg, String::new())),
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+
+        options.player = player.ok_or("pick --bot, --script or --replay")?;
+        if let (Player::Replay, Some(flag)) = (options.player, game_flags.first()) {
+            return Err(format!("{} cannot be combined with --replay", flag));
+        }
+        Ok(options)
+    }
+
+    // A replay brings its own rules, seed and inputs, a script only the inputs
+    fn replay(&self) -> Result<Replay, String> {
+        let path = &self.path;
+        let read = || std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err));
+        let mut rules = self.mode.ruleset();
+        for (flag, value) in self.rule_flags.iter() {
+            rules.apply_flag(flag, value)?;
+        }
+        let mut replay = Replay::new(rules, self.generator.unwrap_or_else(|| self.mode.generator()), self.seed);
+
+        match self.player {
+            Player::Bot => (),
+            Player::Script => {
+                replay.events = replay::parse_events(&read()?).map_err(|err| format!("{}: {}", path, err))?;
+                replay.frames = replay.events.last().map_or(0, |&(frame, _)| frame);
+            },
+            Player::Replay => {
+                replay = read()?.parse().map_err(|err| format!("{}: {}", path, err))?;
+            },
+        }
+        Ok(replay)
+    }
+}
+
+fn main() {
+    let options = Options::from_args().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    });
+    let replay = options.replay().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+
+    let seed = replay.seed;
+    let mut game = replay.start();
+    let result = sim::run(options.player, replay, options.pieces, &mut game);
+
+    match options.format {
+        Format::Text => println!("{}", sim::text_summary(seed, &game)),
+        Format::Json => println!("{}", sim::json_summary(seed, &game)),
+    }
+
+    // A desynced replay still prints where it got to, but fails
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    }
+}
@@ -0,0 +1,162 @@
+// A simple bot for trying out rules without a player. It looks at every spot
+// the falling piece can reach by rotating once and then sliding, and hard
+// drops into the one that leaves the best looking board. Its moves are the
+// same actions a player would press, so bot games play out like any other.
+
+use crate::controls::Action;
+use crate::game::Game;
+use crate::grid::Grid;
+use crate::piece::Piece;
+
+// How much each feature of the board after the drop counts, from Yiyuan
+// Lee's tuned weights
+const HEIGHT_WEIGHT: f64 = -0.510066;
+const LINES_WEIGHT: f64 = 0.760666;
+const HOLES_WEIGHT: f64 = -0.35663;
+const BUMPINESS_WEIGHT: f64 = -0.184483;
+
+// Anything sticking up out of the visible rows is about to top out
+const OVERFLOW_PENALTY: f64 = -1000.0;
+
+// The rotations to try, with what to press to get there
+const ROTATIONS: [&[Action]; 4] = [&[], &[Action::RotateCw], &[Action::Rotate180], &[Action::RotateCcw]];
+
+fn quarter_turns(action: Action) -> i8 {
+    match action {
+        Action::RotateCw => 1,
+        Action::Rotate180 => 2,
+        Action::RotateCcw => -1,
+        _ => 0,
+    }
+}
+
+// The actions that place the game's falling piece where the bot wants it,
+// ending with a hard drop. Nothing when there's no piece to move.
+pub fn plan(game: &Game) -> Option<Vec<Action>> {
+    game.piece().map(|piece| best_move(game.grid(), piece))
+}
+
+pub fn best_move(grid: &Grid, piece: &Piece) -> Vec<Action> {
+    let mut best = (f64::NEG_INFINITY, Vec::new());
+
+    for rotation in ROTATIONS.iter() {
+        let mut rotated = piece.clone();
+        if !rotation.iter().all(|&action| rotated.turn(grid, quarter_turns(action)).is_some()) {
+            continue;
+        }
+
+        for &action in [Action::MoveLeft, Action::MoveRight].iter() {
+            let mut moved = rotated.clone();
+            let mut actions = rotation.to_vec();
+            loop {
+                let score = evaluate(grid, &moved);
+                if score > best.0 {
+                    let mut placement = actions.clone();
+                    placement.push(Action::HardDrop);
+                    best = (score, placement);
+                }
+
+                match action {
+                    Action::MoveLeft => moved.move_left(),
+                    _ => moved.move_right(),
+                }
+                if !grid.fits(&moved) {
+                    break;
+                }
+                actions.push(action);
+            }
+        }
+    }
+
+    best.1
+}
+
+// Drops the piece and scores the board it leaves behind, higher is better
+fn evaluate(grid: &Grid, piece: &Piece) -> f64 {
+    let mut landed = piece.clone();
+    for _ in 0..grid.drop_distance(piece) {
+        landed.move_down();
+    }
+
+    let mut after = grid.clone();
+    after.lock(&landed);
+    let lines = after.clear_lines();
+
+    let visible = after.height() as i16;
+    let mut heights = Vec::with_capacity(after.width());
+    let mut holes = 0;
+    for x in 0..after.width() as i16 {
+        let top = (after.top()..visible).find(|&y| after.is_occupied((x, y).into())).unwrap_or(visible);
+        heights.push(visible - top);
+        holes += (top..visible).filter(|&y| !after.is_occupied((x, y).into())).count();
+    }
+
+    let height: i16 = heights.iter().sum();
+    let bumpiness: i16 = heights.windows(2).map(|pair| (pair[0] - pair[1]).abs()).sum();
+    let overflow = heights.iter().any(|&height| height > visible);
+
+    HEIGHT_WEIGHT * height as f64
+        + LINES_WEIGHT * lines as f64
+        + HOLES_WEIGHT * holes as f64
+        + BUMPINESS_WEIGHT * bumpiness as f64
+        + if overflow { OVERFLOW_PENALTY } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GamePhase;
+    use crate::gamepad::ActionEvent;
+    use crate::generator::BagGenerator;
+    use crate::piece::PieceKind;
+    use crate::replay;
+    use crate::rules::Ruleset;
+
+    #[test]
+    fn takes_the_tetris() {
+        let grid = Grid::from_rows(&[
+            "..........",
+            "..........",
+            "..........",
+            "..........",
+            "#########.",
+            "#########.",
+            "#########.",
+            "#########.",
+        ]);
+        let piece = Piece::spawn(PieceKind::I, &grid);
+        let actions = best_move(&grid, &piece);
+
+        let mut placed = piece.clone();
+        for &action in actions.iter() {
+            match action {
+                Action::MoveLeft => placed.move_left(),
+                Action::MoveRight => placed.move_right(),
+                Action::HardDrop => (),
+                _ => { placed.turn(&grid, quarter_turns(action)); },
+            }
+        }
+        let mut after = grid.clone();
+        for _ in 0..after.drop_distance(&placed) {
+            placed.move_down();
+        }
+        after.lock(&placed);
+        assert_eq!(after.clear_lines(), 4);
+        assert!(after.is_empty());
+    }
+
+    #[test]
+    fn keeps_a_game_going() {
+        let mut game = Game::new(Ruleset::default(), Box::new(BagGenerator::new(5)));
+        while game.pieces() < 200 && game.phase() == GamePhase::Playing {
+            for action in plan(&game).unwrap() {
+                replay::apply(&mut game, ActionEvent::Press(action));
+                replay::apply(&mut game, ActionEvent::Release(action));
+            }
+            game.tick();
+        }
+
+        assert_eq!(game.phase(), GamePhase::Playing);
+        assert!(game.lines() >= 70);
+    }
+}
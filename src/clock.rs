@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+// The simulation always steps in frames of this rate, whatever the display does
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+// After a long stall only this many frames are caught up, the rest is dropped
+// rather than fast forwarding through it
+pub const MAX_CATCH_UP_FRAMES: u32 = 10;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+// Turns elapsed wall-clock time into a whole number of fixed frames. Time
+// that doesn't make up a full frame is carried over to the next call, kept
+// as nanoseconds times the rate so nothing is lost to rounding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameClock {
+    rate: u32,
+    accumulator: u128,
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        FrameClock::new(FRAMES_PER_SECOND)
+    }
+}
+
+impl FrameClock {
+    pub fn new(rate: u32) -> FrameClock {
+        FrameClock {
+            rate: rate.max(1),
+            accumulator: 0,
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    // Adds the elapsed time and returns how many frames should run for it
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed.as_nanos() * self.rate as u128;
+
+        let frames = self.accumulator / NANOS_PER_SECOND;
+        self.accumulator %= NANOS_PER_SECOND;
+        frames.min(MAX_CATCH_UP_FRAMES as u128) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_second_is_sixty_frames() {
+        let mut clock = FrameClock::default();
+        assert_eq!(clock.advance(Duration::from_millis(100)), 6);
+        let frames: u32 = (0..9).map(|_| clock.advance(Duration::from_millis(100))).sum();
+        assert_eq!(frames, 54);
+    }
+
+    #[test]
+    fn partial_frames_carry_over() {
+        let mut clock = FrameClock::default();
+        assert_eq!(clock.advance(Duration::from_millis(10)), 0);
+        assert_eq!(clock.advance(Duration::from_millis(10)), 1);
+        assert_eq!(clock.advance(Duration::from_millis(14)), 1);
+
+        // 1/60 s doesn't fit in whole nanoseconds, the remainder still adds up
+        let mut clock = FrameClock::default();
+        let frames: u32 = (0..600).map(|_| clock.advance(Duration::from_nanos(16_666_667))).sum();
+        assert_eq!(frames, 600);
+    }
+
+    #[test]
+    fn jitter_doesnt_change_the_frame_count() {
+        let mut steady = FrameClock::default();
+        let mut jittery = FrameClock::default();
+        let steady_frames: u32 = (0..100).map(|_| steady.advance(Duration::from_millis(15))).sum();
+        let jittery_frames: u32 = (0..50)
+            .map(|_| jittery.advance(Duration::from_millis(4)) + jittery.advance(Duration::from_millis(26)))
+            .sum();
+        assert_eq!(steady_frames, jittery_frames);
+    }
+
+    #[test]
+    fn long_stalls_are_capped() {
+        let mut clock = FrameClock::default();
+        assert_eq!(clock.advance(Duration::from_secs(5)), MAX_CATCH_UP_FRAMES);
+        assert_eq!(clock.advance(Duration::from_millis(1)), 0);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+// Everything the player can ask for, whatever device it comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    Hold,
+    Pause,
+    Restart,
+    ToggleGhost,
+}
+
+impl Action {
+    pub const ALL: [Action; 11] = [
+        Action::MoveLeft, Action::MoveRight, Action::SoftDrop, Action::HardDrop, Action::RotateCw,
+        Action::RotateCcw, Action::Rotate180, Action::Hold, Action::Pause, Action::Restart, Action::ToggleGhost,
+    ];
+
+    // The name used for the action in the controls file
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::SoftDrop => "soft_drop",
+            Action::HardDrop => "hard_drop",
+            Action::RotateCw => "rotate_cw",
+            Action::RotateCcw => "rotate_ccw",
+            Action::Rotate180 => "rotate_180",
+            Action::Hold => "hold",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::ToggleGhost => "toggle_ghost",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Action::ALL.iter().copied().find(|action| action.name() == name)
+            .ok_or_else(|| format!("unknown action '{}'", name))
+    }
+}
+
+// Which inputs trigger which action. K is whatever the frontend uses to tell
+// keys or buttons apart; each one maps to a single action but an action can
+// have any number of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bindings<K: Eq + Hash> {
+    actions: HashMap<K, Action>,
+}
+
+impl<K: Eq + Hash> Default for Bindings<K> {
+    fn default() -> Self {
+        Bindings { actions: HashMap::new() }
+    }
+}
+
+impl<K: Eq + Hash + Clone> Bindings<K> {
+    pub fn new(bindings: &[(K, Action)]) -> Bindings<K> {
+        let mut result = Bindings::default();
+        for (key, action) in bindings.iter() {
+            result.bind(key.clone(), *action);
+        }
+        result
+    }
+
+    // Binding a key again moves it to the new action
+    pub fn bind(&mut self, key: K, action: Action) {
+        self.actions.insert(key, action);
+    }
+
+    pub fn unbind_action(&mut self, action: Action) {
+        self.actions.retain(|_, bound| *bound != action);
+    }
+
+    pub fn action(&self, key: &K) -> Option<Action> {
+        self.actions.get(key).copied()
+    }
+
+    pub fn keys_for(&self, action: Action) -> Vec<&K> {
+        self.actions.iter().filter(|(_, bound)| **bound == action).map(|(key, _)| key).collect()
+    }
+
+    // Reads a table of `action = ["Key", ...]` entries from `section` of a
+    // TOML document, on top of the defaults. Actions the file doesn't mention
+    // keep their default keys. A key listed under two actions is an error
+    // rather than quietly going to whichever comes last. Errors name the
+    // entry that's wrong.
+    pub fn load(defaults: &Bindings<K>, text: &str, section: &str, parse_key: impl Fn(&str) -> Option<K>)
+        -> Result<Bindings<K>, String>
+    {
+        let document = text.parse::<toml::Value>().map_err(|err| err.to_string())?;
+        let table = match document.get(section) {
+            Some(toml::Value::Table(table)) => table,
+            Some(_) => return Err(format!("[{}] should be a table", section)),
+            None => return Ok(defaults.clone()),
+        };
+
+        let mut bindings = defaults.clone();
+        let mut listed = HashMap::new();
+        for (name, keys) in table.iter() {
+            let action: Action = name.parse().map_err(|err| format!("[{}] {}", section, err))?;
+            let keys = match keys {
+                toml::Value::Array(keys) => keys,
+                _ => return Err(format!("[{}] {} should be a list of names", section, name)),
+            };
+
+            bindings.unbind_action(action);
+            for key in keys.iter() {
+                let parsed = key.as_str().and_then(&parse_key)
+                    .ok_or_else(|| format!("[{}] {}: unknown key {}", section, name, key))?;
+                match listed.insert(parsed.clone(), action) {
+                    Some(other) if other != action =>
+                        return Err(format!("[{}] {}: key {} is also bound to {}", section, name, key, other)),
+                    _ => bindings.bind(parsed, action),
+                }
+            }
+        }
+        Ok(bindings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Bindings<String> {
+        Bindings::new(&[
+            ("Left".to_string(), Action::MoveLeft),
+            ("Right".to_string(), Action::MoveRight),
+            ("Z".to_string(), Action::RotateCcw),
+            ("X".to_string(), Action::RotateCw),
+            ("Up".to_string(), Action::RotateCw),
+        ])
+    }
+
+    fn parse_key(name: &str) -> Option<String> {
+        if name.len() == 1 || ["Left", "Right", "Up", "Down", "Space"].contains(&name) {
+            Some(name.to_string())
+        } else {
+            None
+        }
+    }
+
+    fn load(text: &str) -> Result<Bindings<String>, String> {
+        Bindings::load(&defaults(), text, "keys", parse_key)
+    }
+
+    #[test]
+    fn action_names_round_trip() {
+        for &action in Action::ALL.iter() {
+            assert_eq!(action.name().parse(), Ok(action));
+        }
+        assert!("jump".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn several_keys_can_share_an_action() {
+        let bindings = defaults();
+        let mut keys = bindings.keys_for(Action::RotateCw);
+        keys.sort();
+        assert_eq!(keys, vec!["Up", "X"]);
+        assert_eq!(bindings.action(&"Up".to_string()), Some(Action::RotateCw));
+        assert_eq!(bindings.action(&"Q".to_string()), None);
+    }
+
+    #[test]
+    fn file_overrides_only_the_actions_it_lists() {
+        let bindings = load("[keys]\nrotate_cw = [\"D\"]\nhard_drop = [\"Space\", \"Down\"]\n").unwrap();
+
+        assert_eq!(bindings.keys_for(Action::RotateCw), vec!["D"]);
+        assert_eq!(bindings.action(&"X".to_string()), None);
+        assert_eq!(bindings.action(&"Down".to_string()), Some(Action::HardDrop));
+        assert_eq!(bindings.action(&"Left".to_string()), Some(Action::MoveLeft));
+    }
+
+    #[test]
+    fn missing_section_keeps_the_defaults() {
+        assert_eq!(load("").unwrap(), defaults());
+        assert_eq!(load("[buttons]\nhold = [\"A\"]\n").unwrap(), defaults());
+    }
+
+    #[test]
+    fn errors_name_the_bad_entry() {
+        assert_eq!(load("[keys]\nspin = [\"A\"]\n"), Err("[keys] unknown action 'spin'".to_string()));
+        assert_eq!(load("[keys]\nhold = \"C\"\n"), Err("[keys] hold should be a list of names".to_string()));
+        assert_eq!(load("[keys]\nhold = [\"C\", \"Shift\"]\n"),
+            Err("[keys] hold: unknown key \"Shift\"".to_string()));
+        assert!(load("[keys\n").is_err());
+    }
+
+    #[test]
+    fn a_key_can_only_be_listed_once() {
+        assert_eq!(load("[keys]\nhold = [\"C\"]\nrotate_cw = [\"X\", \"C\"]\n"),
+            Err("[keys] rotate_cw: key \"C\" is also bound to hold".to_string()));
+        assert!(load("[keys]\nhold = [\"C\", \"C\"]\n").is_ok());
+    }
+}
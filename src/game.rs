@@ -0,0 +1,1219 @@
+use std::collections::VecDeque;
+
+use crate::generator::PieceGenerator;
+use crate::gravity::{Gravity, CELL};
+use crate::grid::Grid;
+use crate::piece::{Piece, PieceKind};
+use crate::rules::{Ruleset, MAX_HEIGHT, MAX_HIDDEN_ROWS, MAX_PREVIEW, MAX_WIDTH, MIN_HEIGHT, MIN_PREVIEW, MIN_WIDTH};
+use crate::scoring::{ClearKind, Scoring};
+use crate::shift::{AutoShift, Direction};
+use crate::spin::{self, LastAction, Spin, SpinKind};
+
+// Discrete player actions the game reacts to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    MoveLeft,
+    MoveRight,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    Hold,
+    HardDrop,
+}
+
+// Holding soft drop makes gravity this many times faster
+const SOFT_DROP_FACTOR: u32 = 20;
+
+// What happened when a piece locked, for scoring and announcements
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockResult {
+    pub lines: usize,
+    pub spin: Option<Spin>,
+    // The lines cleared took every last block on the board with them
+    pub perfect_clear: bool,
+}
+
+impl LockResult {
+    // A T-spin with no lines is a T-spin zero, with three a T-spin triple
+    pub fn t_spin(&self) -> Option<SpinKind> {
+        self.spin.filter(|spin| spin.piece == PieceKind::T).map(|spin| spin.kind)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GamePhase {
+    Playing,
+    GameOver,
+}
+
+// A whole game of RustyCubes: the board, the falling piece and the stats.
+// It only moves forward when it's given an input or a frame tick.
+#[derive(Debug)]
+pub struct Game {
+    rules: Ruleset,
+    grid: Grid,
+    piece: Option<Piece>,
+    next: VecDeque<PieceKind>,
+    hold: Option<PieceKind>,
+    // Only one hold is allowed until the next piece locks
+    can_hold: bool,
+    generator: Box<dyn PieceGenerator>,
+    phase: GamePhase,
+    // Set while the player holds soft drop, gravity steps then score points
+    soft_drop: bool,
+    shift: AutoShift,
+    // How far the piece has fallen into the next cell, in 1/CELL steps
+    fall_progress: u32,
+    // Frames the piece has spent on the ground, locks at rules.lock_delay
+    lock_frames: u32,
+    // Lock delay restarts used since the piece reached its lowest row
+    lock_resets: u32,
+    // Set once a move or rotation on the ground finds no resets left, the
+    // piece then locks as soon as it's back on the ground
+    out_of_resets: bool,
+    lowest_row: i16,
+    last_action: LastAction,
+    last_lock: Option<LockResult>,
+    scoring: Scoring,
+    level: u32,
+    lines: usize,
+    pieces: usize,
+    perfect_clears: usize,
+    // Frames played so far, every timer in the game counts these
+    frame: u64,
+}
+
+impl Game {
+    pub fn new(mut rules: Ruleset, generator: Box<dyn PieceGenerator>) -> Game {
+        rules.preview = rules.preview.clamp(MIN_PREVIEW, MAX_PREVIEW);
+        rules.width = rules.width.clamp(MIN_WIDTH, MAX_WIDTH);
+        rules.height = rules.height.clamp(MIN_HEIGHT, MAX_HEIGHT);
+        rules.hidden_rows = rules.hidden_rows.min(MAX_HIDDEN_ROWS);
+
+        let mut game = Game {
+            next: VecDeque::with_capacity(rules.preview),
+            grid: Grid::new(rules.width, rules.height, rules.hidden_rows),
+            piece: None,
+            hold: None,
+            can_hold: true,
+            generator,
+            phase: GamePhase::Playing,
+            soft_drop: false,
+            shift: AutoShift::default(),
+            fall_progress: 0,
+            lock_frames: 0,
+            lock_resets: 0,
+            out_of_resets: false,
+            lowest_row: 0,
+            last_action: LastAction::None,
+            last_lock: None,
+            scoring: Scoring::new(rules.scoring),
+            level: 1,
+            lines: 0,
+            pieces: 0,
+            perfect_clears: 0,
+            frame: 0,
+            rules,
+        };
+        game.fill_queue();
+        game.spawn_next();
+        game
+    }
+
+    // Starts over with the same rules and kind of generator, reseeded
+    pub fn restart(&mut self, seed: u64) {
+        self.generator.reset(seed);
+        self.grid = Grid::new(self.rules.width, self.rules.height, self.rules.hidden_rows);
+        self.next.clear();
+        self.hold = None;
+        self.can_hold = true;
+        self.phase = GamePhase::Playing;
+        self.soft_drop = false;
+        self.shift = AutoShift::default();
+        self.fall_progress = 0;
+        self.last_lock = None;
+        self.scoring = Scoring::new(self.rules.scoring);
+        self.level = 1;
+        self.lines = 0;
+        self.pieces = 0;
+        self.perfect_clears = 0;
+        self.frame = 0;
+        self.fill_queue();
+        self.spawn_next();
+    }
+
+    pub fn rules(&self) -> &Ruleset {
+        &self.rules
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn piece(&self) -> Option<&Piece> {
+        self.piece.as_ref()
+    }
+
+    // Where the falling piece would land if it were hard dropped right now
+    pub fn ghost_piece(&self) -> Option<Piece> {
+        let mut ghost = self.piece.clone()?;
+        for _ in 0..self.grid.drop_distance(&ghost) {
+            ghost.move_down();
+        }
+        Some(ghost)
+    }
+
+    // The upcoming pieces, the one that spawns next first
+    pub fn next_pieces(&self) -> &VecDeque<PieceKind> {
+        &self.next
+    }
+
+    pub fn hold_piece(&self) -> Option<PieceKind> {
+        self.hold
+    }
+
+    pub fn can_hold(&self) -> bool {
+        self.can_hold
+    }
+
+    pub fn phase(&self) -> GamePhase {
+        self.phase
+    }
+
+    pub fn score(&self) -> u64 {
+        self.scoring.score()
+    }
+
+    // Combo, back-to-back and the last clear on top of the score itself
+    pub fn scoring(&self) -> &Scoring {
+        &self.scoring
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    // How fast the piece is falling right now, soft drop included
+    pub fn gravity(&self) -> Gravity {
+        let gravity = self.rules.gravity.gravity(self.level);
+        if self.soft_drop {
+            Gravity((gravity.0 * SOFT_DROP_FACTOR).min(Gravity::TWENTY_G.0))
+        } else {
+            gravity
+        }
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    pub fn pieces(&self) -> usize {
+        self.pieces
+    }
+
+    pub fn perfect_clears(&self) -> usize {
+        self.perfect_clears
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // How the most recent lock went, None until the first piece locks
+    pub fn last_lock(&self) -> Option<LockResult> {
+        self.last_lock
+    }
+
+    // Applies a player action to the falling piece, returns whether it did anything
+    pub fn input(&mut self, input: Input) -> bool {
+        if self.phase == GamePhase::GameOver {
+            return false;
+        }
+
+        match input {
+            Input::Hold => return self.hold(),
+            Input::HardDrop => return self.hard_drop(),
+            _ => (),
+        }
+
+        let grid = &self.grid;
+        let piece = match &mut self.piece {
+            Some(piece) => piece,
+            None => return false,
+        };
+
+        let grounded = is_grounded(piece, grid);
+        let rotate = |piece: &mut Piece, quarter_turns| {
+            piece.turn(grid, quarter_turns).map(|kick| LastAction::Rotate { kick })
+        };
+        let action = match input {
+            Input::MoveLeft => try_move(piece, grid, Piece::move_left).then(|| LastAction::Move),
+            Input::MoveRight => try_move(piece, grid, Piece::move_right).then(|| LastAction::Move),
+            Input::RotateCw => rotate(piece, 1),
+            Input::RotateCcw => rotate(piece, -1),
+            Input::Rotate180 => rotate(piece, 2),
+            Input::Hold | Input::HardDrop => None,
+        };
+
+        let action = match action {
+            Some(action) => action,
+            None => return false,
+        };
+        self.last_action = action;
+
+        // Moving on the ground buys more time, but only so many times
+        if grounded {
+            if self.lock_resets < self.rules.lock_resets {
+                self.lock_resets += 1;
+                self.lock_frames = 0;
+            }
+            self.out_of_resets = self.lock_resets >= self.rules.lock_resets;
+        }
+        true
+    }
+
+    // How many frames the piece has been waiting on the ground
+    pub fn lock_frames(&self) -> u32 {
+        self.lock_frames
+    }
+
+    // Holding a direction moves the piece once, then auto repeats from tick
+    pub fn press_shift(&mut self, direction: Direction) {
+        self.shift.press(direction);
+        self.input(shift_input(direction));
+    }
+
+    pub fn release_shift(&mut self, direction: Direction) {
+        self.shift.release(direction);
+    }
+
+    pub fn is_soft_dropping(&self) -> bool {
+        self.soft_drop
+    }
+
+    // While soft drop is held gravity is faster and every row the piece
+    // falls is worth points
+    pub fn set_soft_drop(&mut self, soft_drop: bool) {
+        self.soft_drop = soft_drop;
+    }
+
+    // Sends the piece straight down onto the stack and locks it there
+    fn hard_drop(&mut self) -> bool {
+        let mut piece = match self.piece.take() {
+            Some(piece) => piece,
+            None => return false,
+        };
+
+        let distance = self.grid.drop_distance(&piece);
+        for _ in 0..distance {
+            piece.move_down();
+        }
+        if distance > 0 {
+            self.last_action = LastAction::Move;
+        }
+        self.scoring.hard_drop(distance as u32);
+        self.lock_piece(&piece);
+        true
+    }
+
+    // Stashes the falling piece and brings back the held one, or the next one
+    // in the queue the first time round. The swapped in piece starts over from
+    // its spawn position.
+    fn hold(&mut self) -> bool {
+        if !self.can_hold {
+            return false;
+        }
+
+        let piece = match self.piece.take() {
+            Some(piece) => piece,
+            None => return false,
+        };
+
+        self.can_hold = false;
+        match self.hold.replace(piece.kind()) {
+            Some(kind) => self.spawn_piece(Piece::spawn(kind, &self.grid)),
+            None => self.spawn_next(),
+        }
+        true
+    }
+
+    // Advances one frame. Held directions auto repeat first, then the piece
+    // falls however many whole cells of gravity it has built up. Once it's on
+    // the ground it locks after the lock delay, or straight away if it has
+    // used up its resets.
+    pub fn tick(&mut self) {
+        if self.phase == GamePhase::GameOver {
+            return;
+        }
+        self.frame += 1;
+
+        if let Some((direction, cells)) = self.shift.tick(self.rules.das, self.rules.arr) {
+            for _ in 0..cells {
+                if !self.input(shift_input(direction)) {
+                    break;
+                }
+            }
+        }
+
+        let mut piece = match self.piece.take() {
+            Some(piece) => piece,
+            None => return,
+        };
+
+        self.fall_progress += self.gravity().0;
+        while self.fall_progress >= CELL {
+            self.fall_progress -= CELL;
+            if !try_move(&mut piece, &self.grid, Piece::move_down) {
+                self.fall_progress = 0;
+                break;
+            }
+            self.last_action = LastAction::Move;
+            if self.soft_drop {
+                self.scoring.soft_drop(1);
+            }
+            if piece.position().y > self.lowest_row {
+                self.lowest_row = piece.position().y;
+                self.lock_resets = 0;
+                self.out_of_resets = false;
+            }
+        }
+
+        if !is_grounded(&piece, &self.grid) {
+            self.lock_frames = 0;
+            self.piece = Some(piece);
+            return;
+        }
+
+        self.lock_frames += 1;
+        if self.lock_frames >= self.rules.lock_delay || self.out_of_resets {
+            self.lock_piece(&piece);
+        } else {
+            self.piece = Some(piece);
+        }
+    }
+
+    fn fill_queue(&mut self) {
+        while self.next.len() < self.rules.preview {
+            self.next.push_back(self.generator.next());
+        }
+    }
+
+    // Takes the front of the queue and tops it back up from the generator
+    fn spawn_next(&mut self) {
+        let kind = self.next.pop_front().unwrap();
+        self.fill_queue();
+        self.spawn_piece(Piece::spawn(kind, &self.grid));
+    }
+
+    // Block out: the game ends when a new piece can't be placed at its spawn
+    fn spawn_piece(&mut self, piece: Piece) {
+        if !self.rules.das_carry {
+            self.shift.discharge();
+        }
+        self.fall_progress = 0;
+        self.lock_frames = 0;
+        self.lock_resets = 0;
+        self.out_of_resets = false;
+        self.lowest_row = piece.position().y;
+        self.last_action = LastAction::None;
+        if self.grid.overlaps(&piece) {
+            self.phase = GamePhase::GameOver;
+        }
+        self.piece = Some(piece);
+    }
+
+    fn lock_piece(&mut self, piece: &Piece) {
+        // Spins are judged on the board as it was before the piece went in
+        let spin = spin::detect(piece, &self.grid, self.last_action, self.rules.all_spin);
+        self.grid.lock(piece);
+        self.pieces += 1;
+        self.can_hold = true;
+        let cleared = self.grid.clear_lines();
+        self.lines += cleared;
+
+        let perfect_clear = cleared > 0 && self.grid.is_empty();
+        let result = LockResult { lines: cleared, spin, perfect_clear };
+        self.scoring.lock(ClearKind::from_lock(cleared, result.t_spin()), self.level);
+        if perfect_clear {
+            self.perfect_clears += 1;
+            self.scoring.perfect_clear(cleared, self.level);
+        }
+        self.last_lock = Some(result);
+        self.level = self.level.max(1 + (self.lines / self.rules.lines_per_level.max(1)) as u32);
+
+        // Lock out: the piece came to rest without entering the visible
+        // playfield, or poked out above the hidden rows
+        let top = self.grid.top();
+        if piece.is_above_visible_area() || piece.cells().iter().any(|pos| pos.y < top) {
+            self.phase = GamePhase::GameOver;
+            return;
+        }
+
+        self.spawn_next();
+    }
+}
+
+// Moves the piece only if it still fits afterwards
+fn try_move(piece: &mut Piece, grid: &Grid, step: fn(&mut Piece)) -> bool {
+    let mut moved = piece.clone();
+    step(&mut moved);
+    if !grid.fits(&moved) {
+        return false;
+    }
+    *piece = moved;
+    true
+}
+
+fn shift_input(direction: Direction) -> Input {
+    match direction {
+        Direction::Left => Input::MoveLeft,
+        Direction::Right => Input::MoveRight,
+    }
+}
+
+// Whether the piece is resting on the stack or the floor
+fn is_grounded(piece: &Piece, grid: &Grid) -> bool {
+    let mut below = piece.clone();
+    below.move_down();
+    !grid.fits(&below)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::GeneratorKind;
+    use crate::gravity::GravityCurve;
+    use crate::grid::{DEFAULT_HEIGHT, DEFAULT_WIDTH};
+    use crate::piece::{GridPosition, PieceKind};
+    use crate::scoring::GUIDELINE_SCORING;
+
+    // Most tests run at 1G without lock delay, so every tick is one row and
+    // pieces lock the frame they land
+    fn one_g_rules() -> Ruleset {
+        Ruleset { gravity: GravityCurve::Custom(vec![Gravity::ONE_G]), lock_delay: 0, ..Ruleset::default() }
+    }
+
+    // An I standing on end with its top block at (x, y)
+    fn upright_i(x: i16, y: i16) -> Piece {
+        let mut piece = Piece::new(x - 2, y, PieceKind::I);
+        piece.set_rotation(1);
+        piece
+    }
+
+    fn new_game() -> Game {
+        Game::new(one_g_rules(), GeneratorKind::Bag.build(0))
+    }
+
+    fn game_with(piece: Piece) -> Game {
+        let mut game = new_game();
+        game.piece = Some(piece);
+        game
+    }
+
+    #[test]
+    fn spawning_onto_the_stack_ends_the_game() {
+        let mut game = new_game();
+        for y in 0..4 {
+            game.grid.fill_line_except(y, 0);
+        }
+
+        game.spawn_piece(Piece::new(4, 0, PieceKind::T));
+        assert_eq!(game.phase(), GamePhase::GameOver);
+    }
+
+    #[test]
+    fn locking_above_the_playfield_ends_the_game() {
+        let mut game = new_game();
+
+        game.lock_piece(&Piece::new(4, -4, PieceKind::O));
+        assert_eq!(game.phase(), GamePhase::GameOver);
+        assert_eq!(game.pieces(), 1);
+    }
+
+    #[test]
+    fn the_stack_can_grow_into_the_hidden_rows() {
+        let mut game = new_game();
+        for y in 1..DEFAULT_HEIGHT as i16 {
+            game.grid.fill_line_except(y, 0);
+        }
+
+        // Sticks out of the visible rows but lands partly inside them
+        game.lock_piece(&upright_i(0, -3));
+        assert_eq!(game.phase(), GamePhase::Playing);
+        assert!(game.grid().is_occupied((0, -3).into()));
+        assert_eq!(game.piece().unwrap().position().y, -1);
+    }
+
+    #[test]
+    fn topping_out_past_the_hidden_rows_ends_the_game() {
+        let rules = Ruleset { hidden_rows: 2, ..one_g_rules() };
+        let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+        for y in 1..DEFAULT_HEIGHT as i16 {
+            game.grid.fill_line_except(y, 0);
+        }
+
+        game.lock_piece(&upright_i(0, -3));
+        assert_eq!(game.phase(), GamePhase::GameOver);
+    }
+
+    #[test]
+    fn pieces_spawn_partly_in_the_hidden_rows() {
+        let game = new_game();
+        assert_eq!(game.piece().unwrap().position().y, -1);
+        assert!(!game.piece().unwrap().is_above_visible_area());
+
+        let rules = Ruleset { hidden_rows: 0, ..one_g_rules() };
+        let game = Game::new(rules, GeneratorKind::Bag.build(0));
+        assert_eq!(game.piece().unwrap().position().y, 0);
+    }
+
+    #[test]
+    fn game_over_ignores_inputs_and_gravity() {
+        let mut game = game_with(Piece::new(4, 5, PieceKind::T));
+        game.phase = GamePhase::GameOver;
+
+        assert!(!game.input(Input::MoveLeft));
+        game.tick();
+        assert_eq!(game.piece().unwrap().position(), GridPosition::from((4, 5)));
+    }
+
+    #[test]
+    fn restart_starts_a_fresh_game() {
+        let mut game = new_game();
+        game.grid.fill_line_except(DEFAULT_HEIGHT as i16 - 1, 0);
+        game.phase = GamePhase::GameOver;
+        game.lines = 12;
+        game.pieces = 30;
+
+        game.restart(0);
+        assert_eq!(game.phase(), GamePhase::Playing);
+        assert_eq!(game.lines(), 0);
+        assert_eq!(game.pieces(), 0);
+        assert_eq!(game.grid(), &Grid::default());
+        assert!(game.piece().is_some());
+    }
+
+    #[test]
+    fn pieces_spawn_in_generator_order() {
+        let mut expected = GeneratorKind::History.build(9);
+        let mut game = Game::new(Ruleset::default(), GeneratorKind::History.build(9));
+
+        for _ in 0..10 {
+            let piece = game.piece().unwrap().clone();
+            assert_eq!(piece, Piece::spawn(expected.next(), game.grid()));
+            game.lock_piece(&game.ghost_piece().unwrap());
+            game.grid = Grid::default();
+        }
+    }
+
+    #[test]
+    fn restart_with_the_same_seed_replays_the_same_pieces() {
+        let mut game = Game::new(Ruleset::default(), GeneratorKind::Bag.build(4));
+        let first = game.piece().unwrap().kind();
+
+        game.lock_piece(&game.piece().unwrap().clone());
+        game.restart(4);
+        assert_eq!(game.piece().unwrap().kind(), first);
+    }
+
+    #[test]
+    fn queue_shows_the_upcoming_pieces_in_order() {
+        let mut expected = GeneratorKind::Bag.build(2);
+        let rules = Ruleset { preview: 3, ..Ruleset::default() };
+        let mut game = Game::new(rules, GeneratorKind::Bag.build(2));
+
+        assert_eq!(game.piece().unwrap().kind(), expected.next());
+        let mut upcoming: Vec<PieceKind> = (0..3).map(|_| expected.next()).collect();
+        for _ in 0..20 {
+            assert_eq!(game.next_pieces().iter().copied().collect::<Vec<_>>(), upcoming);
+
+            game.lock_piece(&game.ghost_piece().unwrap());
+            game.grid = Grid::default();
+
+            assert_eq!(game.piece().unwrap().kind(), upcoming.remove(0));
+            upcoming.push(expected.next());
+        }
+    }
+
+    #[test]
+    fn queue_length_is_kept_within_limits() {
+        for &(preview, expected) in [(0, MIN_PREVIEW), (1, 1), (6, 6), (9, MAX_PREVIEW)].iter() {
+            let rules = Ruleset { preview, ..Ruleset::default() };
+            let mut game = Game::new(rules, GeneratorKind::Uniform.build(0));
+            assert_eq!(game.next_pieces().len(), expected);
+
+            game.restart(1);
+            assert_eq!(game.next_pieces().len(), expected);
+        }
+    }
+
+    #[test]
+    fn first_hold_takes_the_next_piece() {
+        let mut game = game_with(Piece::new(2, 7, PieceKind::T));
+        let next = game.next_pieces()[0];
+
+        assert!(game.input(Input::Hold));
+        assert_eq!(game.hold_piece(), Some(PieceKind::T));
+        assert_eq!(game.piece(), Some(&Piece::spawn(next, game.grid())));
+        assert!(!game.can_hold());
+    }
+
+    #[test]
+    fn hold_swaps_and_resets_to_spawn() {
+        let mut game = game_with(Piece::new(2, 7, PieceKind::T));
+        game.hold = Some(PieceKind::I);
+
+        assert!(game.input(Input::RotateCw));
+        assert!(game.input(Input::Hold));
+        assert_eq!(game.hold_piece(), Some(PieceKind::T));
+        assert_eq!(game.piece(), Some(&Piece::spawn(PieceKind::I, game.grid())));
+    }
+
+    #[test]
+    fn only_one_hold_per_lock() {
+        let mut game = game_with(Piece::new(2, 7, PieceKind::T));
+
+        assert!(game.input(Input::Hold));
+        let held = game.piece().unwrap().clone();
+        assert!(!game.input(Input::Hold));
+        assert_eq!(game.piece(), Some(&held));
+        assert_eq!(game.hold_piece(), Some(PieceKind::T));
+
+        game.lock_piece(&Piece::new(0, DEFAULT_HEIGHT as i16 - 2, held.kind()));
+        assert!(game.can_hold());
+        assert!(game.input(Input::Hold));
+        assert_eq!(game.piece(), Some(&Piece::spawn(PieceKind::T, game.grid())));
+    }
+
+    #[test]
+    fn restart_empties_the_hold() {
+        let mut game = new_game();
+        assert!(game.input(Input::Hold));
+
+        game.restart(0);
+        assert_eq!(game.hold_piece(), None);
+        assert!(game.can_hold());
+    }
+
+    #[test]
+    fn hard_drop_lands_on_the_stack_and_locks() {
+        let mut game = game_with(Piece::new(0, 0, PieceKind::O));
+        game.grid.set((1, 15).into(), Some(PieceKind::I));
+
+        assert!(game.input(Input::HardDrop));
+        assert_eq!(game.pieces(), 1);
+        assert!(game.grid().is_occupied((0, 14).into()));
+        assert!(game.grid().is_occupied((1, 13).into()));
+        assert!(!game.grid().is_occupied((0, 15).into()));
+        assert_eq!(game.score(), 13 * GUIDELINE_SCORING.hard_drop);
+        assert_eq!(game.piece().unwrap().position(), GridPosition::from((4, -1)));
+    }
+
+    // A T one rotation away from a T-spin double, nothing under it but the slot
+    fn t_spin_double_setup() -> Game {
+        let mut piece = Piece::new(3, 2, PieceKind::T);
+        piece.set_rotation(1);
+        let mut game = game_with(piece);
+        game.grid = Grid::from_rows(&[
+            "..........",
+            "..........",
+            "...#......",
+            "###...####",
+            "####.#####",
+        ]);
+        game
+    }
+
+    #[test]
+    fn rotating_into_the_slot_scores_a_t_spin_double() {
+        let mut game = t_spin_double_setup();
+
+        assert!(game.input(Input::RotateCw));
+        assert!(game.input(Input::HardDrop));
+        let result = game.last_lock().unwrap();
+        assert_eq!(result.lines, 2);
+        assert_eq!(result.t_spin(), Some(SpinKind::Full));
+        assert_eq!(game.score(), 1200);
+        assert_eq!(game.scoring().last_clear().unwrap().label, "T-Spin Double");
+    }
+
+    #[test]
+    fn falling_after_the_rotation_loses_the_spin() {
+        // A T against the left wall, its nub pointing into the stack
+        let game_with_t_at = |y| {
+            let mut piece = Piece::new(-1, y, PieceKind::T);
+            piece.set_rotation(1);
+            let mut game = game_with(piece);
+            game.grid = Grid::from_rows(&[
+                "..........",
+                "..........",
+                "..########",
+                ".#########",
+            ]);
+            game.last_action = LastAction::Rotate { kick: 0 };
+            game
+        };
+
+        let mut game = game_with_t_at(1);
+        game.tick();
+        assert_eq!(game.last_lock().unwrap().t_spin(), Some(SpinKind::Mini));
+
+        let mut game = game_with_t_at(0);
+        game.tick();
+        assert_eq!(game.last_lock(), Some(LockResult { lines: 2, spin: None, perfect_clear: false }));
+    }
+
+    #[test]
+    fn all_spins_are_reported_when_the_rules_allow_them() {
+        for &all_spin in [false, true].iter() {
+            let rules = Ruleset { all_spin, ..one_g_rules() };
+            let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+            game.grid = Grid::from_rows(&[
+                "..........",
+                "#####.####",
+                "##..######",
+                "#..#######",
+            ]);
+            game.piece = Some(Piece::new(1, 2, PieceKind::S));
+            game.last_action = LastAction::Rotate { kick: 1 };
+
+            game.tick();
+            let result = game.last_lock().unwrap();
+            assert_eq!(result.lines, 2);
+            assert_eq!(result.spin.map(|spin| spin.piece), if all_spin { Some(PieceKind::S) } else { None });
+            assert_eq!(result.t_spin(), None);
+        }
+    }
+
+    // Hard drops each (kind, rotation, x) from the top of an empty board,
+    // checking it lands with its box at row y of the bottom four
+    fn drop_pieces(placements: &[(PieceKind, i8, i16, i16)]) -> Game {
+        let mut game = new_game();
+        let floor = DEFAULT_HEIGHT as i16 - 4;
+        for &(kind, rotation, x, y) in placements.iter() {
+            let mut piece = Piece::new(x, 0, kind);
+            piece.set_rotation(rotation);
+            game.piece = Some(piece);
+
+            assert_eq!(game.ghost_piece().unwrap().position(), GridPosition::from((x, floor + y)), "{:?}", kind);
+            assert!(game.input(Input::HardDrop));
+        }
+        game
+    }
+
+    #[test]
+    fn four_line_perfect_clears() {
+        let openers: [[(PieceKind, i8, i16, i16); 10]; 2] = [
+            [
+                (PieceKind::L, 3, 3, 1), (PieceKind::Z, 3, 2, 1), (PieceKind::J, 2, 2, -1),
+                (PieceKind::O, 0, 0, 2), (PieceKind::O, 0, 0, 0), (PieceKind::T, 0, 5, 2),
+                (PieceKind::T, 1, 4, 0), (PieceKind::S, 3, 7, 1), (PieceKind::L, 2, 6, -1),
+                (PieceKind::I, 1, 7, 0),
+            ],
+            [
+                (PieceKind::J, 0, 1, 2), (PieceKind::Z, 1, 1, 0), (PieceKind::L, 1, 0, 0),
+                (PieceKind::T, 0, 4, 2), (PieceKind::T, 1, 3, 0), (PieceKind::S, 1, 5, 1),
+                (PieceKind::L, 2, 5, -1), (PieceKind::O, 0, 8, 2), (PieceKind::O, 0, 8, 0),
+                (PieceKind::I, 1, -2, 0),
+            ],
+        ];
+
+        for opener in openers.iter() {
+            let before = drop_pieces(&opener[..9]);
+            assert_eq!(before.lines(), 0);
+
+            // Both finish with an I dropped 16 rows into the last column
+            let game = drop_pieces(opener);
+            let result = game.last_lock().unwrap();
+            assert_eq!(result.lines, 4);
+            assert!(result.perfect_clear);
+            assert!(game.grid().is_empty());
+            assert_eq!(game.perfect_clears(), 1);
+            assert_eq!(game.score(), before.score() + 800 + 2000 + 16 * GUIDELINE_SCORING.hard_drop);
+        }
+    }
+
+    #[test]
+    fn hard_drop_in_place_scores_nothing() {
+        let mut game = game_with(Piece::new(0, DEFAULT_HEIGHT as i16 - 2, PieceKind::O));
+
+        assert!(game.input(Input::HardDrop));
+        assert_eq!(game.pieces(), 1);
+        assert_eq!(game.score(), 0);
+    }
+
+    #[test]
+    fn soft_drop_scores_every_row_fallen() {
+        let mut game = Game::new(Ruleset::default(), GeneratorKind::Bag.build(0));
+        game.piece = Some(Piece::new(4, 0, PieceKind::O));
+
+        for _ in 0..60 {
+            game.tick();
+        }
+        assert_eq!(game.piece().unwrap().position(), GridPosition::from((4, 1)));
+        assert_eq!(game.score(), 0);
+
+        // Level 1 falls a cell a second, twenty times that is one every three frames
+        game.set_soft_drop(true);
+        for _ in 0..15 {
+            game.tick();
+        }
+        assert_eq!(game.score(), 5 * GUIDELINE_SCORING.soft_drop);
+
+        game.set_soft_drop(false);
+        game.tick();
+        assert_eq!(game.score(), 5 * GUIDELINE_SCORING.soft_drop);
+        assert_eq!(game.piece().unwrap().position(), GridPosition::from((4, 6)));
+    }
+
+    #[test]
+    fn ghost_follows_the_piece_down_to_the_stack() {
+        let mut game = game_with(Piece::new(0, 0, PieceKind::T));
+        game.grid.set((1, 10).into(), Some(PieceKind::O));
+
+        let ghost = game.ghost_piece().unwrap();
+        assert_eq!(ghost.position(), GridPosition::from((0, 8)));
+        assert_eq!(ghost.rotation(), 0);
+
+        assert!(game.input(Input::MoveRight));
+        assert!(game.input(Input::MoveRight));
+        let ghost = game.ghost_piece().unwrap();
+        assert_eq!(ghost.position(), GridPosition::from((2, DEFAULT_HEIGHT as i16 - 2)));
+
+        assert!(game.input(Input::RotateCw));
+        let ghost = game.ghost_piece().unwrap();
+        assert_eq!(ghost.rotation(), 1);
+        assert_eq!(ghost.position(), GridPosition::from((2, DEFAULT_HEIGHT as i16 - 3)));
+    }
+
+    #[test]
+    fn half_turn_rotates_in_place_only() {
+        let mut game = game_with(Piece::new(4, 5, PieceKind::T));
+        assert!(game.input(Input::Rotate180));
+        assert_eq!(game.piece().unwrap().rotation(), 2);
+        assert_eq!(game.piece().unwrap().position(), GridPosition::from((4, 5)));
+
+        // Flat on the floor there's no room to flip the T over
+        let mut game = game_with(Piece::new(4, DEFAULT_HEIGHT as i16 - 2, PieceKind::T));
+        assert!(!game.input(Input::Rotate180));
+    }
+
+    #[test]
+    fn moves_stop_at_the_walls() {
+        let mut game = game_with(Piece::new(0, 5, PieceKind::O));
+
+        assert!(!game.input(Input::MoveLeft));
+        for _ in 0..DEFAULT_WIDTH - 2 {
+            assert!(game.input(Input::MoveRight));
+        }
+        assert!(!game.input(Input::MoveRight));
+        assert_eq!(game.piece().unwrap().position(), GridPosition::from((DEFAULT_WIDTH as i16 - 2, 5)));
+    }
+
+    #[test]
+    fn walls_and_floor_follow_the_board_size() {
+        for &(width, height) in [(4, 20), (20, 20), (10, 40), (MIN_WIDTH - 1, MAX_HEIGHT + 1)].iter() {
+            let rules = Ruleset { width, height, ..one_g_rules() };
+            let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+            let (width, height) = (game.grid().width() as i16, game.grid().height() as i16);
+            assert!(width >= MIN_WIDTH as i16 && height <= MAX_HEIGHT as i16);
+
+            game.piece = Some(Piece::new(0, 0, PieceKind::O));
+            while game.input(Input::MoveRight) {}
+            assert_eq!(game.piece().unwrap().position().x, width - 2);
+
+            game.input(Input::HardDrop);
+            assert!(game.grid().is_occupied((width - 1, height - 1).into()));
+            assert!(!game.grid().is_occupied((width - 1, height - 3).into()));
+        }
+    }
+
+    #[test]
+    fn gravity_drops_the_piece_and_locks_it_on_the_floor() {
+        let mut game = game_with(Piece::new(4, 0, PieceKind::O));
+
+        for _ in 0..DEFAULT_HEIGHT - 3 {
+            game.tick();
+            assert_eq!(game.pieces(), 0);
+        }
+        game.tick();
+
+        assert_eq!(game.pieces(), 1);
+        assert!(game.grid().is_occupied((4, DEFAULT_HEIGHT as i16 - 1).into()));
+        assert!(game.grid().is_occupied((5, DEFAULT_HEIGHT as i16 - 2).into()));
+        assert_eq!(game.phase(), GamePhase::Playing);
+    }
+
+    #[test]
+    fn locking_counts_cleared_lines() {
+        let mut game = game_with(Piece::new(0, DEFAULT_HEIGHT as i16 - 2, PieceKind::O));
+        game.grid.fill_line_except(DEFAULT_HEIGHT as i16 - 1, 0);
+        game.grid.set((1, DEFAULT_HEIGHT as i16 - 1).into(), None);
+        game.grid.fill_line_except(DEFAULT_HEIGHT as i16 - 2, 0);
+        game.grid.set((1, DEFAULT_HEIGHT as i16 - 2).into(), None);
+
+        game.tick();
+        assert_eq!(game.lines(), 2);
+        assert_eq!(game.scoring().last_clear().unwrap().label, "Double");
+
+        // Nothing else was on the board, so that was a perfect clear too
+        assert_eq!(game.grid(), &Grid::default());
+        assert!(game.last_lock().unwrap().perfect_clear);
+        assert_eq!(game.score(), 300 + 1200);
+    }
+
+    #[test]
+    fn sub_cell_gravity_waits_between_rows() {
+        let rules = Ruleset { gravity: GravityCurve::Custom(vec![Gravity::from_frames_per_cell(4)]), ..Ruleset::default() };
+        let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+        game.piece = Some(Piece::new(4, 0, PieceKind::O));
+
+        for frame in 1..=12 {
+            game.tick();
+            assert_eq!(game.piece().unwrap().position().y, frame / 4);
+        }
+    }
+
+    #[test]
+    fn twenty_g_lands_in_a_single_frame() {
+        let rules = Ruleset { gravity: GravityCurve::Custom(vec![Gravity::TWENTY_G]), ..Ruleset::default() };
+        let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+        game.piece = Some(Piece::new(4, 0, PieceKind::O));
+
+        game.tick();
+        assert_eq!(game.piece().unwrap().position(), GridPosition::from((4, DEFAULT_HEIGHT as i16 - 2)));
+        assert_eq!(game.pieces(), 0);
+    }
+
+    fn delayed_game(piece: Piece) -> Game {
+        let rules = Ruleset { lock_delay: 30, lock_resets: 3, ..one_g_rules() };
+        let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+        game.piece = Some(piece);
+        game
+    }
+
+    #[test]
+    fn landed_piece_waits_out_the_lock_delay() {
+        let mut game = delayed_game(Piece::new(4, DEFAULT_HEIGHT as i16 - 2, PieceKind::O));
+
+        for _ in 0..29 {
+            game.tick();
+        }
+        assert_eq!(game.pieces(), 0);
+        assert_eq!(game.lock_frames(), 29);
+
+        game.tick();
+        assert_eq!(game.pieces(), 1);
+    }
+
+    #[test]
+    fn moving_on_the_ground_restarts_the_lock_delay() {
+        let mut game = delayed_game(Piece::new(4, DEFAULT_HEIGHT as i16 - 2, PieceKind::O));
+
+        for _ in 0..20 {
+            game.tick();
+        }
+        assert!(game.input(Input::MoveLeft));
+        assert_eq!(game.lock_frames(), 0);
+
+        for _ in 0..29 {
+            game.tick();
+        }
+        assert_eq!(game.pieces(), 0);
+        game.tick();
+        assert_eq!(game.pieces(), 1);
+    }
+
+    #[test]
+    fn failed_moves_do_not_restart_the_lock_delay() {
+        let mut game = delayed_game(Piece::new(0, DEFAULT_HEIGHT as i16 - 2, PieceKind::O));
+
+        for _ in 0..20 {
+            game.tick();
+        }
+        assert!(!game.input(Input::MoveLeft));
+        assert_eq!(game.lock_frames(), 20);
+    }
+
+    #[test]
+    fn piece_locks_once_its_resets_run_out() {
+        let mut game = delayed_game(Piece::new(4, DEFAULT_HEIGHT as i16 - 2, PieceKind::O));
+
+        for &input in [Input::MoveLeft, Input::MoveRight, Input::MoveLeft].iter() {
+            game.tick();
+            assert!(game.input(input));
+        }
+        assert_eq!(game.pieces(), 0);
+
+        game.tick();
+        assert_eq!(game.pieces(), 1);
+    }
+
+    #[test]
+    fn no_resets_still_waits_out_the_lock_delay() {
+        let rules = Ruleset { lock_delay: 30, lock_resets: 0, ..one_g_rules() };
+        let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+        game.piece = Some(Piece::new(4, DEFAULT_HEIGHT as i16 - 2, PieceKind::O));
+
+        for _ in 0..29 {
+            game.tick();
+        }
+        assert_eq!(game.pieces(), 0);
+        game.tick();
+        assert_eq!(game.pieces(), 1);
+
+        // Any move on the ground then locks it straight away
+        game.piece = Some(Piece::new(4, DEFAULT_HEIGHT as i16 - 4, PieceKind::O));
+        game.tick();
+        assert!(game.input(Input::MoveLeft));
+        assert_eq!(game.pieces(), 1);
+        game.tick();
+        assert_eq!(game.pieces(), 2);
+    }
+
+    #[test]
+    fn falling_to_a_new_row_gives_the_resets_back() {
+        let mut game = delayed_game(Piece::new(0, 10, PieceKind::O));
+        game.grid.set((0, 12).into(), Some(PieceKind::I));
+
+        // Use up the resets on the ledge, then slide off it
+        for _ in 0..2 {
+            game.tick();
+            assert!(game.input(Input::RotateCw));
+        }
+        game.tick();
+        assert!(game.input(Input::MoveRight));
+        assert_eq!(game.pieces(), 0);
+
+        while game.piece().unwrap().position().y < DEFAULT_HEIGHT as i16 - 2 {
+            game.tick();
+        }
+        for &input in [Input::MoveRight, Input::MoveLeft].iter() {
+            game.tick();
+            assert!(game.input(input));
+        }
+        game.tick();
+        assert_eq!(game.pieces(), 0);
+    }
+
+    #[test]
+    fn hard_drop_skips_the_lock_delay() {
+        let mut game = delayed_game(Piece::new(4, 0, PieceKind::O));
+
+        assert!(game.input(Input::HardDrop));
+        assert_eq!(game.pieces(), 1);
+    }
+
+    #[test]
+    fn level_goes_up_every_few_lines_and_speeds_up_gravity() {
+        let rules = Ruleset { lines_per_level: 2, ..Ruleset::default() };
+        let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+        let start = game.gravity();
+
+        for expected in [1, 1, 2, 2].iter() {
+            assert_eq!(game.level(), *expected);
+            game.grid.fill_line_except(DEFAULT_HEIGHT as i16 - 1, 0);
+            game.lock_piece(&Piece::new(0, DEFAULT_HEIGHT as i16 - 2, PieceKind::O));
+            game.grid = Grid::default();
+        }
+        assert_eq!(game.lines(), 4);
+        assert_eq!(game.level(), 3);
+        assert!(game.gravity() > start);
+    }
+
+    #[test]
+    fn same_inputs_on_the_same_frames_play_out_the_same() {
+        let play = || {
+            let mut game = Game::new(Ruleset::default(), GeneratorKind::Bag.build(7));
+            while game.phase() == GamePhase::Playing && game.frame() < 20_000 {
+                match game.frame() % 97 {
+                    10 => { game.input(Input::MoveLeft); },
+                    30 => { game.input(Input::RotateCw); },
+                    60 => game.set_soft_drop(true),
+                    90 => game.set_soft_drop(false),
+                    _ => (),
+                }
+                game.tick();
+            }
+            (game.grid().clone(), game.score(), game.pieces(), game.frame())
+        };
+
+        let first = play();
+        assert!(first.2 > 5);
+        assert_eq!(play(), first);
+    }
+
+    fn shifting_game(das: u32, arr: u32, das_carry: bool) -> Game {
+        let rules = Ruleset { das, arr, das_carry, lock_delay: 30, ..Ruleset::default() };
+        let mut game = Game::new(rules, GeneratorKind::Bag.build(0));
+        game.piece = Some(Piece::new(4, 5, PieceKind::O));
+        game
+    }
+
+    fn x(game: &Game) -> i16 {
+        game.piece().unwrap().position().x
+    }
+
+    #[test]
+    fn held_direction_moves_once_then_auto_repeats() {
+        let mut game = shifting_game(10, 2, true);
+
+        game.press_shift(Direction::Right);
+        assert_eq!(x(&game), 5);
+        for _ in 0..9 {
+            game.tick();
+        }
+        assert_eq!(x(&game), 5);
+
+        game.tick();
+        assert_eq!(x(&game), 6);
+        game.tick();
+        game.tick();
+        assert_eq!(x(&game), 7);
+
+        game.release_shift(Direction::Right);
+        for _ in 0..10 {
+            game.tick();
+        }
+        assert_eq!(x(&game), 7);
+    }
+
+    #[test]
+    fn zero_arr_shifts_to_the_wall_in_one_frame() {
+        let mut game = shifting_game(3, 0, true);
+
+        game.press_shift(Direction::Left);
+        for _ in 0..3 {
+            game.tick();
+        }
+        assert_eq!(x(&game), 0);
+    }
+
+    #[test]
+    fn das_charge_carries_over_to_the_next_piece() {
+        for &(das_carry, expected) in [(true, 0), (false, 4)].iter() {
+            let mut game = shifting_game(5, 0, das_carry);
+            game.press_shift(Direction::Left);
+            for _ in 0..5 {
+                game.tick();
+            }
+
+            assert!(game.input(Input::HardDrop));
+            game.tick();
+            assert_eq!(x(&game), expected, "carry {}", das_carry);
+        }
+    }
+
+    #[test]
+    fn restart_resets_the_score() {
+        let mut game = game_with(Piece::new(0, 0, PieceKind::O));
+        assert!(game.input(Input::HardDrop));
+        assert!(game.score() > 0);
+
+        game.restart(0);
+        assert_eq!(game.score(), 0);
+        assert_eq!(game.level(), 1);
+        assert_eq!(game.frame(), 0);
+        assert_eq!(game.scoring().combo(), None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::controls::{Action, Bindings};
+
+// How far a stick has to be pushed before it counts as held
+pub const DEFAULT_THRESHOLD: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AxisDirection {
+    Negative,
+    Positive,
+}
+
+impl AxisDirection {
+    fn sign(self) -> f32 {
+        match self {
+            AxisDirection::Negative => -1.0,
+            AxisDirection::Positive => 1.0,
+        }
+    }
+}
+
+// Buttons and sticks turned into the same presses and releases the keyboard
+// makes, so the rest of the game doesn't care where they came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionEvent {
+    Press(Action),
+    Release(Action),
+}
+
+// Maps gamepad buttons of type B and axes of type A onto actions. A stick
+// pushed one way past the threshold acts like a held button until it comes
+// back under it.
+#[derive(Clone, Debug)]
+pub struct Gamepad<B: Eq + Hash, A: Eq + Hash> {
+    buttons: Bindings<B>,
+    axes: Bindings<(A, AxisDirection)>,
+    threshold: f32,
+    pushed: HashSet<(A, AxisDirection)>,
+}
+
+impl<B: Eq + Hash + Clone, A: Eq + Hash + Clone> Gamepad<B, A> {
+    pub fn new(buttons: Bindings<B>, axes: Bindings<(A, AxisDirection)>, threshold: f32) -> Gamepad<B, A> {
+        Gamepad {
+            buttons,
+            axes,
+            threshold,
+            pushed: HashSet::new(),
+        }
+    }
+
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    pub fn button_down(&self, button: &B) -> Option<ActionEvent> {
+        self.buttons.action(button).map(ActionEvent::Press)
+    }
+
+    pub fn button_up(&self, button: &B) -> Option<ActionEvent> {
+        self.buttons.action(button).map(ActionEvent::Release)
+    }
+
+    // Only crossing the threshold makes an event, a stick held still past it
+    // doesn't keep pressing
+    pub fn axis(&mut self, axis: A, value: f32) -> Vec<ActionEvent> {
+        let mut events = Vec::new();
+        for &direction in [AxisDirection::Negative, AxisDirection::Positive].iter() {
+            let key = (axis.clone(), direction);
+            let action = match self.axes.action(&key) {
+                Some(action) => action,
+                None => continue,
+            };
+
+            let pushed = value * direction.sign() >= self.threshold;
+            if pushed && self.pushed.insert(key.clone()) {
+                events.push(ActionEvent::Press(action));
+            } else if !pushed && self.pushed.remove(&key) {
+                events.push(ActionEvent::Release(action));
+            }
+        }
+        events
+    }
+
+    // Reads the [buttons] and [axes] tables and the threshold in [gamepad]
+    // from the controls file, on top of the defaults. Axis names are given
+    // with the direction at the end, like "LeftStickX-".
+    pub fn load(defaults: &Gamepad<B, A>, text: &str, parse_button: impl Fn(&str) -> Option<B>,
+        parse_axis: impl Fn(&str) -> Option<A>) -> Result<Gamepad<B, A>, String>
+    {
+        let parse_axis_direction = |name: &str| {
+            let direction = match name.chars().last()? {
+                '-' => AxisDirection::Negative,
+                '+' => AxisDirection::Positive,
+                _ => return None,
+            };
+            parse_axis(&name[..name.len() - 1]).map(|axis| (axis, direction))
+        };
+
+        let buttons = Bindings::load(&defaults.buttons, text, "buttons", parse_button)?;
+        let axes = Bindings::load(&defaults.axes, text, "axes", parse_axis_direction)?;
+
+        let document = text.parse::<toml::Value>().map_err(|err| err.to_string())?;
+        let threshold = match document.get("gamepad").and_then(|gamepad| gamepad.get("threshold")) {
+            None => defaults.threshold,
+            Some(value) => match value.as_float() {
+                Some(threshold) if threshold > 0.0 && threshold < 1.0 => threshold as f32,
+                _ => return Err(format!("[gamepad] threshold should be a number between 0 and 1, not {}", value)),
+            },
+        };
+
+        Ok(Gamepad::new(buttons, axes, threshold))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Button {
+        South,
+        DPadLeft,
+        DPadRight,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Axis {
+        StickX,
+        StickY,
+    }
+
+    fn gamepad() -> Gamepad<Button, Axis> {
+        Gamepad::new(
+            Bindings::new(&[
+                (Button::South, Action::RotateCw),
+                (Button::DPadLeft, Action::MoveLeft),
+                (Button::DPadRight, Action::MoveRight),
+            ]),
+            Bindings::new(&[
+                ((Axis::StickX, AxisDirection::Negative), Action::MoveLeft),
+                ((Axis::StickX, AxisDirection::Positive), Action::MoveRight),
+                ((Axis::StickY, AxisDirection::Negative), Action::SoftDrop),
+            ]),
+            DEFAULT_THRESHOLD,
+        )
+    }
+
+    fn parse_button(name: &str) -> Option<Button> {
+        match name {
+            "South" => Some(Button::South),
+            "DPadLeft" => Some(Button::DPadLeft),
+            "DPadRight" => Some(Button::DPadRight),
+            _ => None,
+        }
+    }
+
+    fn parse_axis(name: &str) -> Option<Axis> {
+        match name {
+            "StickX" => Some(Axis::StickX),
+            "StickY" => Some(Axis::StickY),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn buttons_press_and_release_their_actions() {
+        let pad = gamepad();
+        assert_eq!(pad.button_down(&Button::DPadLeft), Some(ActionEvent::Press(Action::MoveLeft)));
+        assert_eq!(pad.button_up(&Button::DPadLeft), Some(ActionEvent::Release(Action::MoveLeft)));
+        assert_eq!(pad.button_down(&Button::South), Some(ActionEvent::Press(Action::RotateCw)));
+    }
+
+    #[test]
+    fn sticks_act_like_buttons_past_the_threshold() {
+        let mut pad = gamepad();
+
+        assert_eq!(pad.axis(Axis::StickX, 0.3), vec![]);
+        assert_eq!(pad.axis(Axis::StickX, 0.6), vec![ActionEvent::Press(Action::MoveRight)]);
+        assert_eq!(pad.axis(Axis::StickX, 0.9), vec![]);
+        assert_eq!(pad.axis(Axis::StickX, 0.1), vec![ActionEvent::Release(Action::MoveRight)]);
+        assert_eq!(pad.axis(Axis::StickX, 0.0), vec![]);
+    }
+
+    #[test]
+    fn flicking_a_stick_across_releases_before_pressing() {
+        let mut pad = gamepad();
+        pad.axis(Axis::StickX, -1.0);
+
+        assert_eq!(pad.axis(Axis::StickX, 1.0),
+            vec![ActionEvent::Release(Action::MoveLeft), ActionEvent::Press(Action::MoveRight)]);
+    }
+
+    #[test]
+    fn unbound_directions_do_nothing() {
+        let mut pad = gamepad();
+        assert_eq!(pad.axis(Axis::StickY, 1.0), vec![]);
+        assert_eq!(pad.axis(Axis::StickY, -1.0), vec![ActionEvent::Press(Action::SoftDrop)]);
+    }
+
+    #[test]
+    fn loads_bindings_and_threshold() {
+        let text = "[buttons]\nhold = [\"South\"]\n[axes]\nhard_drop = [\"StickY+\"]\n[gamepad]\nthreshold = 0.8\n";
+        let mut pad = Gamepad::load(&gamepad(), text, parse_button, parse_axis).unwrap();
+
+        assert_eq!(pad.threshold(), 0.8);
+        assert_eq!(pad.button_down(&Button::South), Some(ActionEvent::Press(Action::Hold)));
+        assert_eq!(pad.axis(Axis::StickY, 0.7), vec![]);
+        assert_eq!(pad.axis(Axis::StickY, 0.9), vec![ActionEvent::Press(Action::HardDrop)]);
+        assert_eq!(pad.axis(Axis::StickX, -0.9), vec![ActionEvent::Press(Action::MoveLeft)]);
+    }
+
+    #[test]
+    fn load_errors_name_the_bad_entry() {
+        let load = |text: &str| Gamepad::load(&gamepad(), text, parse_button, parse_axis).map(|_| ());
+
+        assert_eq!(load("[axes]\nhold = [\"StickX\"]\n"), Err("[axes] hold: unknown key \"StickX\"".to_string()));
+        assert_eq!(load("[buttons]\nhold = [\"Start\"]\n"), Err("[buttons] hold: unknown key \"Start\"".to_string()));
+        assert_eq!(load("[gamepad]\nthreshold = 2.0\n"),
+            Err("[gamepad] threshold should be a number between 0 and 1, not 2.0".to_string()));
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::piece::PieceKind;
+
+// Decides which piece comes next. Every generator is seeded explicitly so
+// the same seed always produces the same sequence. They use ChaCha8 rather
+// than StdRng, which may change between rand versions and break replays.
+pub trait PieceGenerator: fmt::Debug {
+    fn next(&mut self) -> PieceKind;
+
+    // Starts the sequence over as if freshly built with this seed
+    fn reset(&mut self, seed: u64);
+}
+
+// Deals all seven pieces in a random order before shuffling a new bag,
+// so droughts never last longer than 12 pieces
+#[derive(Debug)]
+pub struct BagGenerator {
+    rng: ChaCha8Rng,
+    bag: Vec<PieceKind>,
+}
+
+impl BagGenerator {
+    pub fn new(seed: u64) -> BagGenerator {
+        BagGenerator {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            bag: Vec::with_capacity(PieceKind::ALL.len()),
+        }
+    }
+}
+
+impl PieceGenerator for BagGenerator {
+    fn next(&mut self) -> PieceKind {
+        if self.bag.is_empty() {
+            self.bag.extend_from_slice(&PieceKind::ALL);
+            self.bag.shuffle(&mut self.rng);
+        }
+        self.bag.pop().unwrap()
+    }
+
+    fn reset(&mut self, seed: u64) {
+        *self = BagGenerator::new(seed);
+    }
+}
+
+// Every piece is equally likely every time, regardless of what came before
+#[derive(Debug)]
+pub struct UniformGenerator {
+    rng: ChaCha8Rng,
+}
+
+impl UniformGenerator {
+    pub fn new(seed: u64) -> UniformGenerator {
+        UniformGenerator {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl PieceGenerator for UniformGenerator {
+    fn next(&mut self) -> PieceKind {
+        self.rng.gen()
+    }
+
+    fn reset(&mut self, seed: u64) {
+        *self = UniformGenerator::new(seed);
+    }
+}
+
+const HISTORY_ROLLS: usize = 6;
+const HISTORY_START: [PieceKind; 4] = [PieceKind::Z, PieceKind::S, PieceKind::S, PieceKind::Z];
+const HISTORY_FIRST_PIECES: [PieceKind; 4] = [PieceKind::I, PieceKind::J, PieceKind::L, PieceKind::T];
+
+// TGM style: remembers the last four pieces and rerolls a few times to avoid
+// dealing one of them again. The first piece is never an S, Z or O.
+#[derive(Debug)]
+pub struct HistoryGenerator {
+    rng: ChaCha8Rng,
+    history: [PieceKind; 4],
+    first: bool,
+}
+
+impl HistoryGenerator {
+    pub fn new(seed: u64) -> HistoryGenerator {
+        HistoryGenerator {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            history: HISTORY_START,
+            first: true,
+        }
+    }
+
+    fn roll(&mut self) -> PieceKind {
+        if self.first {
+            self.first = false;
+            return *HISTORY_FIRST_PIECES.choose(&mut self.rng).unwrap();
+        }
+
+        let mut kind = self.rng.gen();
+        for _ in 1..HISTORY_ROLLS {
+            if !self.history.contains(&kind) {
+                break;
+            }
+            kind = self.rng.gen();
+        }
+        kind
+    }
+}
+
+impl PieceGenerator for HistoryGenerator {
+    fn next(&mut self) -> PieceKind {
+        let kind = self.roll();
+        self.history.rotate_left(1);
+        self.history[3] = kind;
+        kind
+    }
+
+    fn reset(&mut self, seed: u64) {
+        *self = HistoryGenerator::new(seed);
+    }
+}
+
+// The generators that can be picked at startup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeneratorKind {
+    Bag,
+    Uniform,
+    History,
+}
+
+impl Default for GeneratorKind {
+    fn default() -> Self {
+        GeneratorKind::Bag
+    }
+}
+
+impl GeneratorKind {
+    pub fn build(self, seed: u64) -> Box<dyn PieceGenerator> {
+        match self {
+            GeneratorKind::Bag => Box::new(BagGenerator::new(seed)),
+            GeneratorKind::Uniform => Box::new(UniformGenerator::new(seed)),
+            GeneratorKind::History => Box::new(HistoryGenerator::new(seed)),
+        }
+    }
+}
+
+impl fmt::Display for GeneratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            GeneratorKind::Bag => "bag",
+            GeneratorKind::Uniform => "uniform",
+            GeneratorKind::History => "history",
+        })
+    }
+}
+
+impl FromStr for GeneratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bag" => Ok(GeneratorKind::Bag),
+            "uniform" => Ok(GeneratorKind::Uniform),
+            "history" => Ok(GeneratorKind::History),
+            _ => Err(format!("unknown generator '{}', expected bag, uniform or history", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [GeneratorKind; 3] = [GeneratorKind::Bag, GeneratorKind::Uniform, GeneratorKind::History];
+
+    fn deal(generator: &mut dyn PieceGenerator, count: usize) -> Vec<PieceKind> {
+        (0..count).map(|_| generator.next()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_sequence() {
+        for &kind in KINDS.iter() {
+            let first = deal(kind.build(42).as_mut(), 100);
+            let second = deal(kind.build(42).as_mut(), 100);
+            let other = deal(kind.build(43).as_mut(), 100);
+
+            assert_eq!(first, second, "{:?}", kind);
+            assert_ne!(first, other, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn reset_replays_the_sequence() {
+        for &kind in KINDS.iter() {
+            let mut generator = kind.build(7);
+            let first = deal(generator.as_mut(), 30);
+            generator.reset(7);
+
+            assert_eq!(deal(generator.as_mut(), 30), first, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn bag_deals_every_piece_once_per_seven() {
+        let mut generator = BagGenerator::new(1);
+        for _ in 0..20 {
+            let mut bag = deal(&mut generator, 7);
+            bag.sort_by_key(|&kind| kind as usize);
+            assert_eq!(bag, PieceKind::ALL.to_vec());
+        }
+    }
+
+    #[test]
+    fn uniform_deals_every_piece() {
+        let pieces = deal(&mut UniformGenerator::new(3), 700);
+        for kind in PieceKind::ALL.iter() {
+            assert!(pieces.contains(kind), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn history_never_starts_with_s_z_or_o() {
+        for seed in 0..50 {
+            let first = HistoryGenerator::new(seed).next();
+            assert!(HISTORY_FIRST_PIECES.contains(&first), "seed {} dealt {:?}", seed, first);
+        }
+    }
+
+    #[test]
+    fn history_rarely_repeats_recent_pieces() {
+        let count = 7000;
+        let history = deal(&mut HistoryGenerator::new(5), count);
+        let uniform = deal(&mut UniformGenerator::new(5), count);
+
+        let repeats = |pieces: &[PieceKind]| pieces.windows(2).filter(|w| w[0] == w[1]).count();
+        assert!(repeats(&history) * 10 < repeats(&uniform),
+            "history {} vs uniform {}", repeats(&history), repeats(&uniform));
+    }
+
+    #[test]
+    fn parses_generator_names() {
+        assert_eq!("bag".parse::<GeneratorKind>(), Ok(GeneratorKind::Bag));
+        assert_eq!("uniform".parse::<GeneratorKind>(), Ok(GeneratorKind::Uniform));
+        assert_eq!("history".parse::<GeneratorKind>(), Ok(GeneratorKind::History));
+        assert!("random".parse::<GeneratorKind>().is_err());
+        for &kind in KINDS.iter() {
+            assert_eq!(kind.to_string().parse(), Ok(kind));
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Gravity is counted in 1/65536ths of a cell per frame, so slow speeds like
+// one cell every 48 frames stay exact and replays don't drift with floats
+pub const CELL: u32 = 1 << 16;
+
+// How far the falling piece drops every frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Gravity(pub u32);
+
+impl Gravity {
+    pub const ONE_G: Gravity = Gravity(CELL);
+    // The piece lands the frame it spawns
+    pub const TWENTY_G: Gravity = Gravity(20 * CELL);
+
+    // Rounded up so the piece never takes longer than `frames` to fall a cell
+    pub const fn from_frames_per_cell(frames: u32) -> Gravity {
+        Gravity((CELL + frames - 1) / frames)
+    }
+
+    pub fn from_cells_per_frame(cells: f64) -> Gravity {
+        Gravity((cells * CELL as f64).ceil() as u32)
+    }
+
+    pub fn cells_per_frame(self) -> f64 {
+        self.0 as f64 / CELL as f64
+    }
+}
+
+// (0.8 - (level - 1) * 0.007) ^ (level - 1) seconds per cell, for levels 1 to 18
+const GUIDELINE_GRAVITY: [Gravity; 19] = [
+    Gravity(1093), Gravity(1378), Gravity(1769), Gravity(2311), Gravity(3076),
+    Gravity(4169), Gravity(5759), Gravity(8107), Gravity(11635), Gravity(17027),
+    Gravity(25416), Gravity(38709), Gravity(60169), Gravity(95484), Gravity(154743),
+    Gravity(256187), Gravity(433425), Gravity(749597), Gravity::TWENTY_G,
+];
+
+// Frames per cell on the NES, where level 0 is our level 1
+const NES_FRAMES_PER_CELL: [u32; 30] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6,
+    5, 5, 5, 4, 4, 4, 3, 3, 3, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 1,
+];
+
+// Which speed each level plays at. Levels past the end of a table keep its
+// last speed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GravityCurve {
+    Guideline,
+    Nes,
+    // One entry per level, starting at level 1
+    Custom(Vec<Gravity>),
+}
+
+impl Default for GravityCurve {
+    fn default() -> Self {
+        GravityCurve::Guideline
+    }
+}
+
+impl GravityCurve {
+    pub fn gravity(&self, level: u32) -> Gravity {
+        let index = level.max(1) as usize - 1;
+        match self {
+            GravityCurve::Guideline => GUIDELINE_GRAVITY[index.min(GUIDELINE_GRAVITY.len() - 1)],
+            GravityCurve::Nes => {
+                let frames = NES_FRAMES_PER_CELL[index.min(NES_FRAMES_PER_CELL.len() - 1)];
+                Gravity::from_frames_per_cell(frames)
+            },
+            GravityCurve::Custom(table) => match table.last() {
+                Some(&last) => table.get(index).copied().unwrap_or(last),
+                None => Gravity::ONE_G,
+            },
+        }
+    }
+}
+
+// Written the same way FromStr reads it back
+impl fmt::Display for GravityCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GravityCurve::Guideline => f.write_str("guideline"),
+            GravityCurve::Nes => f.write_str("nes"),
+            GravityCurve::Custom(table) => {
+                let speeds: Vec<String> = table.iter().map(|gravity| gravity.cells_per_frame().to_string()).collect();
+                f.write_str(&speeds.join(","))
+            },
+        }
+    }
+}
+
+// Either a curve's name or a comma separated list of cells per frame, one per level
+impl FromStr for GravityCurve {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "guideline" => return Ok(GravityCurve::Guideline),
+            "nes" => return Ok(GravityCurve::Nes),
+            _ => (),
+        }
+
+        let mut table = Vec::new();
+        for speed in name.split(',') {
+            match speed.trim().parse::<f64>() {
+                Ok(cells) if cells > 0.0 && cells <= 20.0 => table.push(Gravity::from_cells_per_frame(cells)),
+                _ => return Err(format!(
+                    "invalid gravity '{}', expected guideline, nes or cells per frame like 0.02,0.5,20", speed)),
+            }
+        }
+        Ok(GravityCurve::Custom(table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // How many frames it takes to fall the first cell
+    fn frames_per_cell(gravity: Gravity) -> u32 {
+        (CELL + gravity.0 - 1) / gravity.0
+    }
+
+    #[test]
+    fn guideline_starts_at_one_cell_per_second_and_ends_at_20g() {
+        let curve = GravityCurve::Guideline;
+        assert_eq!(frames_per_cell(curve.gravity(1)), 60);
+        assert_eq!(curve.gravity(19), Gravity::TWENTY_G);
+        assert_eq!(curve.gravity(50), Gravity::TWENTY_G);
+    }
+
+    #[test]
+    fn curves_only_get_faster() {
+        for curve in [GravityCurve::Guideline, GravityCurve::Nes].iter() {
+            for level in 1..40 {
+                assert!(curve.gravity(level) <= curve.gravity(level + 1), "{:?} level {}", curve, level);
+            }
+        }
+    }
+
+    #[test]
+    fn nes_matches_its_frame_counts() {
+        let curve = GravityCurve::Nes;
+        assert_eq!(frames_per_cell(curve.gravity(1)), 48);
+        assert_eq!(frames_per_cell(curve.gravity(2)), 43);
+        assert_eq!(frames_per_cell(curve.gravity(10)), 6);
+        assert_eq!(frames_per_cell(curve.gravity(30)), 1);
+        assert_eq!(curve.gravity(99), Gravity::ONE_G);
+    }
+
+    #[test]
+    fn custom_curves_hold_their_last_speed() {
+        let curve: GravityCurve = "0.5, 1, 20".parse().unwrap();
+        assert_eq!(curve.gravity(1), Gravity(CELL / 2));
+        assert_eq!(curve.gravity(2), Gravity::ONE_G);
+        assert_eq!(curve.gravity(3), Gravity::TWENTY_G);
+        assert_eq!(curve.gravity(10), Gravity::TWENTY_G);
+    }
+
+    #[test]
+    fn parses_curve_names() {
+        assert_eq!("guideline".parse(), Ok(GravityCurve::Guideline));
+        assert_eq!("nes".parse(), Ok(GravityCurve::Nes));
+        assert!("fast".parse::<GravityCurve>().is_err());
+        assert!("0.5,0".parse::<GravityCurve>().is_err());
+        assert!("30".parse::<GravityCurve>().is_err());
+
+        // Speeds are written out exactly, even ones that don't look round
+        let custom: GravityCurve = "0.25, 0.5,20".parse().unwrap();
+        assert_eq!(custom.to_string(), "0.25,0.5,20");
+        let uneven: GravityCurve = "0.02".parse().unwrap();
+        assert_eq!(uneven.to_string().parse(), Ok(uneven));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::piece::{GridPosition, Piece, PieceKind};
+
+// The guideline playfield is 10 wide and 20 tall, with as many hidden rows
+// again above it
+pub const DEFAULT_WIDTH: usize = 10;
+pub const DEFAULT_HEIGHT: usize = 20;
+pub const DEFAULT_HIDDEN_ROWS: usize = 20;
+
+// The playfield, indexed as cells[x][y] with y pointing down. Row 0 is the
+// top visible row, the hidden rows above it have negative y.
+// Each cell holds the kind of the piece that locked there, if any.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+    hidden_rows: usize,
+    cells: Vec<Vec<Option<PieceKind>>>
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Grid::new(DEFAULT_WIDTH, DEFAULT_HEIGHT, DEFAULT_HIDDEN_ROWS)
+    }
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize, hidden_rows: usize) -> Grid {
+        Grid {
+            width,
+            height,
+            hidden_rows,
+            cells: vec![vec![None; hidden_rows + height]; width],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    // Only counts the visible rows
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn hidden_rows(&self) -> usize {
+        self.hidden_rows
+    }
+
+    // The y of the topmost hidden row
+    pub fn top(&self) -> i16 {
+        -(self.hidden_rows as i16)
+    }
+
+    fn in_bounds(&self, pos: GridPosition) -> bool {
+        pos.x >= 0 && pos.y >= self.top() && pos.x < self.width as i16 && pos.y < self.height as i16
+    }
+
+    fn index(&self, pos: GridPosition) -> (usize, usize) {
+        (pos.x as usize, (pos.y - self.top()) as usize)
+    }
+
+    pub fn get(&self, pos: GridPosition) -> Option<PieceKind> {
+        if !self.in_bounds(pos) {
+            return None;
+        }
+        let (x, y) = self.index(pos);
+        self.cells[x][y]
+    }
+
+    pub fn set(&mut self, pos: GridPosition, kind: Option<PieceKind>) {
+        if self.in_bounds(pos) {
+            let (x, y) = self.index(pos);
+            self.cells[x][y] = kind;
+        }
+    }
+
+    pub fn is_occupied(&self, pos: GridPosition) -> bool {
+        self.get(pos).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|column| column.iter().all(Option::is_none))
+    }
+
+    // Walls and the floor are as solid as the stack. Space above the hidden
+    // rows counts as empty.
+    pub fn is_solid(&self, pos: GridPosition) -> bool {
+        pos.x < 0 || pos.x >= self.width as i16 || pos.y >= self.height as i16 || self.is_occupied(pos)
+    }
+
+    // Whether the piece is inside the walls and floor without touching the stack
+    pub fn fits(&self, piece: &Piece) -> bool {
+        piece.cells().iter().all(|&pos| !self.is_solid(pos))
+    }
+
+    // How many rows the piece can fall before it lands on the stack or the floor
+    pub fn drop_distance(&self, piece: &Piece) -> i16 {
+        let mut dropped = piece.clone();
+        let mut distance = 0;
+        loop {
+            dropped.move_down();
+            if !self.fits(&dropped) {
+                return distance;
+            }
+            distance += 1;
+        }
+    }
+
+    pub fn overlaps(&self, piece: &Piece) -> bool {
+        piece.cells().iter().any(|&pos| self.is_occupied(pos))
+    }
+
+    // Copies the piece's blocks into the grid, dropping any above the hidden rows
+    pub fn lock(&mut self, piece: &Piece) {
+        for &pos in piece.cells().iter() {
+            self.set(pos, Some(piece.kind()));
+        }
+    }
+
+    // Builds a grid with no hidden rows from rows drawn top to bottom, '#'
+    // for a block and '.' for an empty cell
+    #[cfg(test)]
+    pub(crate) fn from_rows(rows: &[&str]) -> Grid {
+        let mut grid = Grid::new(rows[0].len(), rows.len(), 0);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                if cell == '#' {
+                    grid.set((x as i16, y as i16).into(), Some(PieceKind::O));
+                }
+            }
+        }
+        grid
+    }
+
+    // Fills row y with blocks, except for the cell at x = hole
+    #[cfg(test)]
+    pub(crate) fn fill_line_except(&mut self, y: i16, hole: i16) {
+        for x in 0..self.width() as i16 {
+            if x != hole {
+                self.set((x, y).into(), Some(PieceKind::O));
+            }
+        }
+    }
+
+    fn is_line_full(&self, y: usize) -> bool {
+        self.cells.iter().all(|column| column[y].is_some())
+    }
+
+    // Removes every full line and drops everything above it down,
+    // returns how many lines were cleared
+    pub fn clear_lines(&mut self) -> usize {
+        let mut cleared = 0;
+
+        for y in (0..self.hidden_rows + self.height).rev() {
+            if self.is_line_full(y) {
+                cleared += 1;
+                continue;
+            }
+
+            if cleared > 0 {
+                for column in self.cells.iter_mut() {
+                    column[y + cleared] = column[y];
+                }
+            }
+        }
+
+        for column in self.cells.iter_mut() {
+            for cell in column.iter_mut().take(cleared) {
+                *cell = None;
+            }
+        }
+
+        cleared
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTTOM: i16 = DEFAULT_HEIGHT as i16 - 1;
+
+    fn fill_line(grid: &mut Grid, y: i16, kind: PieceKind) {
+        for x in 0..grid.width() as i16 {
+            grid.set((x, y).into(), Some(kind));
+        }
+    }
+
+    fn line_is_empty(grid: &Grid, y: i16) -> bool {
+        (0..grid.width() as i16).all(|x| !grid.is_occupied((x, y).into()))
+    }
+
+    fn occupied(grid: &Grid, x: i16, y: i16) -> bool {
+        grid.is_occupied((x, y).into())
+    }
+
+    #[test]
+    fn no_full_lines_clears_nothing() {
+        let mut grid = Grid::default();
+        grid.fill_line_except(BOTTOM, 3);
+
+        assert_eq!(grid.clear_lines(), 0);
+        assert!(!occupied(&grid, 3, BOTTOM));
+        assert!(occupied(&grid, 4, BOTTOM));
+    }
+
+    #[test]
+    fn clears_single() {
+        let mut grid = Grid::default();
+        fill_line(&mut grid, BOTTOM, PieceKind::I);
+        grid.set((2, BOTTOM - 1).into(), Some(PieceKind::T));
+
+        assert_eq!(grid.clear_lines(), 1);
+        assert_eq!(grid.get((2, BOTTOM).into()), Some(PieceKind::T));
+        assert!(!occupied(&grid, 3, BOTTOM));
+        assert!(line_is_empty(&grid, BOTTOM - 1));
+    }
+
+    #[test]
+    fn clears_double() {
+        let mut grid = Grid::default();
+        fill_line(&mut grid, BOTTOM, PieceKind::I);
+        fill_line(&mut grid, BOTTOM - 1, PieceKind::I);
+        grid.fill_line_except(BOTTOM - 2, 0);
+
+        assert_eq!(grid.clear_lines(), 2);
+        assert!(!occupied(&grid, 0, BOTTOM));
+        assert!(occupied(&grid, 1, BOTTOM));
+        assert!(line_is_empty(&grid, BOTTOM - 1));
+        assert!(line_is_empty(&grid, BOTTOM - 2));
+    }
+
+    #[test]
+    fn clears_triple() {
+        let mut grid = Grid::default();
+        grid.fill_line_except(BOTTOM, 5);
+        for y in BOTTOM - 3..BOTTOM {
+            fill_line(&mut grid, y, PieceKind::L);
+        }
+
+        assert_eq!(grid.clear_lines(), 3);
+        assert!(!occupied(&grid, 5, BOTTOM));
+        assert!(occupied(&grid, 6, BOTTOM));
+        for y in 0..BOTTOM {
+            assert!(line_is_empty(&grid, y));
+        }
+    }
+
+    #[test]
+    fn clears_tetris() {
+        let mut grid = Grid::default();
+        for y in BOTTOM - 3..=BOTTOM {
+            fill_line(&mut grid, y, PieceKind::I);
+        }
+        grid.fill_line_except(BOTTOM - 4, 9);
+
+        assert_eq!(grid.clear_lines(), 4);
+        assert!(!grid.is_empty());
+        assert!(occupied(&grid, 0, BOTTOM));
+        assert!(!occupied(&grid, 9, BOTTOM));
+        for y in 0..BOTTOM {
+            assert!(line_is_empty(&grid, y));
+        }
+    }
+
+    #[test]
+    fn clears_non_adjacent_lines() {
+        let mut grid = Grid::default();
+        fill_line(&mut grid, BOTTOM, PieceKind::S);
+        grid.fill_line_except(BOTTOM - 1, 1);
+        fill_line(&mut grid, BOTTOM - 2, PieceKind::Z);
+        grid.fill_line_except(BOTTOM - 3, 7);
+
+        assert_eq!(grid.clear_lines(), 2);
+
+        // The two partial lines keep their order and land on the floor
+        assert!(!occupied(&grid, 1, BOTTOM));
+        assert!(occupied(&grid, 7, BOTTOM));
+        assert!(occupied(&grid, 1, BOTTOM - 1));
+        assert!(!occupied(&grid, 7, BOTTOM - 1));
+        for y in 0..BOTTOM - 1 {
+            assert!(line_is_empty(&grid, y));
+        }
+    }
+
+    #[test]
+    fn drop_distance_stops_on_the_stack() {
+        let mut grid = Grid::default();
+        grid.set((5, 12).into(), Some(PieceKind::Z));
+
+        assert_eq!(grid.drop_distance(&Piece::new(4, 0, PieceKind::T)), 10);
+        assert_eq!(grid.drop_distance(&Piece::new(0, 0, PieceKind::T)), BOTTOM - 1);
+        assert_eq!(grid.drop_distance(&Piece::new(0, BOTTOM - 1, PieceKind::T)), 0);
+    }
+
+    #[test]
+    fn lock_drops_blocks_above_the_top() {
+        let mut grid = Grid::default();
+        grid.lock(&Piece::new(0, grid.top() - 1, PieceKind::O));
+
+        assert!(occupied(&grid, 0, grid.top()));
+        assert!(occupied(&grid, 1, grid.top()));
+        assert!(line_is_empty(&grid, grid.top() + 1));
+    }
+
+    #[test]
+    fn hidden_rows_hold_blocks_and_clear_like_any_other() {
+        let mut grid = Grid::default();
+        grid.lock(&Piece::new(0, -1, PieceKind::O));
+        assert!(occupied(&grid, 0, -1));
+        assert!(occupied(&grid, 1, 0));
+
+        fill_line(&mut grid, -1, PieceKind::I);
+        fill_line(&mut grid, BOTTOM, PieceKind::I);
+        assert_eq!(grid.clear_lines(), 2);
+        assert!(occupied(&grid, 1, 1));
+        assert!(line_is_empty(&grid, 0));
+        assert!(line_is_empty(&grid, -1));
+        assert!(!grid.is_empty());
+
+        let shallow = Grid::new(10, 20, 2);
+        assert_eq!(shallow.top(), -2);
+        assert!(shallow.fits(&Piece::new(0, -4, PieceKind::O)));
+        assert!(!Grid::new(10, 20, 0).is_occupied((0, -1).into()));
+    }
+
+    #[test]
+    fn bounds_follow_the_size() {
+        for &(width, height) in [(4, 20), (10, 20), (20, 20), (10, 40)].iter() {
+            let grid = Grid::new(width, height, 0);
+            let (right, bottom) = (width as i16 - 1, height as i16 - 1);
+
+            assert!(grid.fits(&Piece::new(right - 1, bottom - 1, PieceKind::O)));
+            assert!(!grid.fits(&Piece::new(right, bottom - 1, PieceKind::O)));
+            assert!(!grid.fits(&Piece::new(right - 1, bottom, PieceKind::O)));
+            assert_eq!(grid.drop_distance(&Piece::new(0, 0, PieceKind::O)), bottom - 1);
+
+            let mut grid = grid;
+            grid.set((right + 1, 0).into(), Some(PieceKind::T));
+            assert_eq!(grid, Grid::new(width, height, 0));
+        }
+    }
+
+    #[test]
+    fn clears_lines_across_the_whole_width() {
+        for &width in [4, 20].iter() {
+            let mut grid = Grid::new(width, 8, 0);
+            fill_line(&mut grid, 7, PieceKind::I);
+            grid.fill_line_except(6, width as i16 - 1);
+
+            assert_eq!(grid.clear_lines(), 1);
+            assert!(occupied(&grid, 0, 7));
+            assert!(!occupied(&grid, width as i16 - 1, 7));
+            assert!(line_is_empty(&grid, 6));
+        }
+    }
+}
@@ -0,0 +1,19 @@
+// Game rules for RustyCubes. Nothing in here knows about ggez, so the whole
+// game can be driven and tested without opening a window.
+
+pub mod bot;
+pub mod clock;
+pub mod controls;
+pub mod game;
+pub mod gamepad;
+pub mod generator;
+pub mod gravity;
+pub mod grid;
+pub mod menu;
+pub mod piece;
+pub mod replay;
+pub mod rules;
+pub mod scoring;
+pub mod shift;
+pub mod sim;
+pub mod spin;
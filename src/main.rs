@@ -1,8 +1,17 @@
 use ggez::*;
+use ggez::event::{Axis, Button, GamepadId, KeyCode};
 use ggez::graphics::Color;
-use std::time::{Duration, Instant};
-use rand::distributions::{Distribution, Standard};
-use rand::Rng;
+
+use rustycubes::clock::FrameClock;
+use rustycubes::controls::{Action, Bindings};
+use rustycubes::game::{Game, GamePhase};
+use rustycubes::gamepad::{ActionEvent, AxisDirection, Gamepad, DEFAULT_THRESHOLD};
+use rustycubes::generator::GeneratorKind;
+use rustycubes::menu::{Menu, MenuEvent, MenuInput};
+use rustycubes::rules::{Mode, Ruleset, SWITCH_FLAGS, VALUE_FLAGS};
+use rustycubes::grid::Grid;
+use rustycubes::piece::{shape_for, GridPosition, Piece, PieceKind};
+use rustycubes::replay::{self, Playback, Replay};
 
 const COLOR_CYAN_LIGHT: Color = Color {r: 50.0/255.0, g: 200.0/255.0, b: 240.0/255.0, a: 1.0};
 const COLOR_CYAN_DARK: Color = Color {r: 25.0/255.0, g: 175.0/255.0, b: 215.0/255.0, a: 1.0};
@@ -10,9 +19,11 @@ const COLOR_CYAN_DARK: Color = Color {r: 25.0/255.0, g: 175.0/255.0, b: 215.0/25
 const COLOR_BLUE_LIGHT: Color = Color {r: 108.0/255.0, g: 125.0/255.0, b: 200.0/255.0, a: 1.0};
 const COLOR_BLUE_DARK: Color = Color {r: 70.0/255.0, g: 85.0/255.0, b: 160.0/255.0, a: 1.0};
 
+#[allow(clippy::eq_op)]
 const COLOR_ORANGE_LIGHT: Color = Color {r: 255.0/255.0, g: 140.0/255.0, b: 55.0/255.0, a: 1.0};
 const COLOR_ORANGE_DARK: Color = Color {r: 225.0/255.0, g: 105.0/255.0, b: 20.0/255.0, a: 1.0};
 
+#[allow(clippy::eq_op)]
 const COLOR_YELLOW_LIGHT: Color = Color {r: 255.0/255.0, g: 232.0/255.0, b: 25.0/255.0, a: 1.0};
 const COLOR_YELLOW_DARK: Color = Color {r: 230.0/255.0, g: 195.0/255.0, b: 0.0/255.0, a: 1.0};
 
@@ -22,74 +33,62 @@ const COLOR_GREEN_DARK: Color = Color {r: 45.0/255.0, g: 165.0/255.0, b: 45.0/25
 const COLOR_PURPLE_LIGHT: Color = Color {r: 195.0/255.0, g: 92.0/255.0, b: 175.0/255.0, a: 1.0};
 const COLOR_PURPLE_DARK: Color = Color {r: 150.0/255.0, g: 60.0/255.0, b: 135.0/255.0, a: 1.0};
 
+#[allow(clippy::eq_op)]
 const COLOR_RED_LIGHT: Color = Color {r: 255.0/255.0, g: 65.0/255.0, b: 70.0/255.0, a: 1.0};
 const COLOR_RED_DARK: Color = Color {r: 215.0/255.0, g: 20.0/255.0, b: 25.0/255.0, a: 1.0};
 
 
-const COLOR_WHITE: Color = ggez::graphics::WHITE;
+const COLOR_GREY_LIGHT: Color = Color {r: 130.0/255.0, g: 130.0/255.0, b: 130.0/255.0, a: 1.0};
+const COLOR_GREY_DARK: Color = Color {r: 90.0/255.0, g: 90.0/255.0, b: 90.0/255.0, a: 1.0};
 
-// Here we're defining how many quickly we want our game to update. This will be
-// important later so that we don't have our snake fly across the screen because
-// it's moving a full tile every frame.
-const UPDATES_PER_SECOND: f32 = 6.0;
-// And we get the milliseconds of delay that this update rate corresponds to.
-const MILLIS_PER_UPDATE: u64 = (1.0 / UPDATES_PER_SECOND * 1000.0) as u64;
+const COLOR_WHITE: Color = ggez::graphics::WHITE;
 
 const BLOCK_SIZE: f32 = 32.0;
 const BLOCK_INNER_SIZE: f32 = BLOCK_SIZE - 1.0;
 
-// grid[rows][cols]
-const GRID_ROWS: usize = 10;
-const GRID_COLS: usize = 20;
 const GRID_SIZE: f32 = BLOCK_SIZE + 4.0;
 const GRID_POS_X: f32 = 250.0;
 const GRID_POS_Y: f32 = 80.0;
 
-const WINDOW_WIDTH: f32 = 1024.0;
-const WINDOW_HEIGHT: f32 = 920.0;
+// How see-through the ghost piece's fill and outline are
+const GHOST_FILL_ALPHA: f32 = 0.15;
+const GHOST_OUTLINE_ALPHA: f32 = 0.6;
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-struct GridPosition {
-    x: i16,
-    y: i16
-}
+// The upcoming pieces are listed to the right of the grid, the held one to the left
+const PANEL_GAP: f32 = 40.0;
+const PREVIEW_BLOCK_SIZE: f32 = 24.0;
+const HOLD_POS_X: f32 = GRID_POS_X - PANEL_GAP - 4.0 * PREVIEW_BLOCK_SIZE;
+const SCORE_POS_Y: f32 = GRID_POS_Y + 160.0;
 
-impl GridPosition {
-    pub fn new(x: i16, y: i16) -> GridPosition {
-        GridPosition {x, y}
-    }
-}
+// Lives in the user's config directory, e.g. ~/.config/rustycubes on Linux
+const CONTROLS_FILE: &str = "controls.toml";
 
-impl From<GridPosition> for graphics::Rect {
-    fn from(pos: GridPosition) -> graphics::Rect {
-        graphics::Rect::new_i32(
-            pos.x as i32 * GRID_SIZE as i32 + GRID_POS_X as i32 + 1,
-            pos.y as i32 * GRID_SIZE as i32 + GRID_POS_Y as i32 + 1,
-            GRID_SIZE as i32, GRID_SIZE as i32)
-    }
-}
+// Every game is saved here when it ends, under the user's data directory
+const REPLAY_DIR: &str = "replays";
 
-impl From<(i16, i16)> for GridPosition {
-    fn from(pos: (i16, i16)) -> Self {
-        GridPosition { x: pos.0, y: pos.1 }
-    }
+// How many frames a replay plays per frame while fast-forwarding
+const FAST_FORWARD: u32 = 4;
+
+const WINDOW_WIDTH: f32 = 1024.0;
+const WINDOW_HEIGHT: f32 = 920.0;
+
+fn panel_pos_x(grid: &Grid) -> f32 {
+    GRID_POS_X + grid.width() as f32 * GRID_SIZE + PANEL_GAP
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum Direction {
-    LEFT,
-    RIGHT
+// Boards too big for the window get the whole view scaled down to fit
+fn fit_screen(ctx: &mut Context, grid: &Grid) -> GameResult<()> {
+    let width = panel_pos_x(grid) + 4.0 * PREVIEW_BLOCK_SIZE + PANEL_GAP;
+    let height = GRID_POS_Y * 2.0 + grid.height() as f32 * GRID_SIZE;
+    let scale = (width / WINDOW_WIDTH).max(height / WINDOW_HEIGHT).max(1.0);
+    graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, WINDOW_WIDTH * scale, WINDOW_HEIGHT * scale))
 }
 
-#[derive(Clone, Copy, Debug)]
-enum PieceKind {
-    I,
-    J,
-    L,
-    O,
-    S,
-    T,
-    Z,
+fn cell_rect(pos: GridPosition) -> graphics::Rect {
+    graphics::Rect::new_i32(
+        pos.x as i32 * GRID_SIZE as i32 + GRID_POS_X as i32 + 1,
+        pos.y as i32 * GRID_SIZE as i32 + GRID_POS_Y as i32 + 1,
+        GRID_SIZE as i32, GRID_SIZE as i32)
 }
 
 fn color_for_kind(kind: PieceKind) -> (Color, Color) {
@@ -104,627 +103,968 @@ fn color_for_kind(kind: PieceKind) -> (Color, Color) {
     }
 }
 
-#[derive(Clone, Debug)]
-struct Block {
-    kind: PieceKind,
-    position: GridPosition,
-    offset: GridPosition,
-    rect: graphics::Rect,
-    outer_mesh: graphics::Mesh,
-    inner_mesh: graphics::Mesh,
-    active: bool,
-    render: bool,
-}
-
-impl Block {
-    pub fn new(ctx: &mut Context, x: i16, y: i16, kind: PieceKind) -> Block {
-        let position = GridPosition::from((x, y));
-        let rect = graphics::Rect::from(position);
-
-        let (dark_color, light_color) = color_for_kind(kind);
-
-        let block = Block {
-            kind: kind,
-            position: position,
-            rect: rect,
-            offset: (0, 0).into(),
-            outer_mesh: graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), 
-                    rect, dark_color).unwrap(),
-            inner_mesh: graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), 
-                graphics::Rect::new(rect.x+2.0, rect.y+2.0, BLOCK_INNER_SIZE, BLOCK_INNER_SIZE),
-                light_color).unwrap(),
-            active: false,
-            render: false,
-        };
+// A block mesh for every colour at one size, built once and moved into place
+// when drawn rather than made again every frame
+struct BlockMeshes {
+    // In PieceKind::ALL order, then the greyed out one
+    meshes: Vec<graphics::Mesh>,
+}
+
+impl BlockMeshes {
+    fn new(ctx: &mut Context, size: f32) -> GameResult<BlockMeshes> {
+        let mut colors: Vec<(Color, Color)> = PieceKind::ALL.iter().map(|&kind| color_for_kind(kind)).collect();
+        colors.push((COLOR_GREY_DARK, COLOR_GREY_LIGHT));
 
-        block
+        let inner_size = size - (GRID_SIZE - BLOCK_INNER_SIZE);
+        let meshes = colors.iter().map(|&(dark_color, light_color)| {
+            graphics::MeshBuilder::new()
+                .rectangle(graphics::DrawMode::fill(), graphics::Rect::new(0.0, 0.0, size, size), dark_color)
+                .rectangle(graphics::DrawMode::fill(), graphics::Rect::new(2.0, 2.0, inner_size, inner_size), light_color)
+                .build(ctx)
+        }).collect::<GameResult<Vec<_>>>()?;
+
+        Ok(BlockMeshes { meshes })
     }
 
-    pub fn empty(ctx: &mut Context, kind: PieceKind) -> Block {
-        Block::new(ctx, 0, 0, kind)
+    fn draw(&self, ctx: &mut Context, kind: PieceKind, greyed: bool, x: f32, y: f32) -> GameResult<()> {
+        let index = if greyed { PieceKind::ALL.len() } else { kind as usize };
+        graphics::draw(ctx, &self.meshes[index], graphics::DrawParam::default().dest([x, y]))
     }
+}
 
-    fn update(&mut self, ctx: &mut Context, parent_position: GridPosition) {
-        if !self.active { return; }
+fn draw_block(ctx: &mut Context, blocks: &BlockMeshes, pos: GridPosition, kind: PieceKind) -> GameResult<()> {
+    let rect = cell_rect(pos);
+    blocks.draw(ctx, kind, false, rect.x, rect.y)
+}
 
-        self.position.x = parent_position.x + self.offset.x;
-        self.position.y = parent_position.y + self.offset.y;
+fn draw_grid(ctx: &mut Context, blocks: &BlockMeshes, grid: &Grid) -> GameResult<()> {
+    for x in 0..grid.width() as i16 {
+        for y in 0..grid.height() as i16 {
+            let pos = GridPosition::from((x, y));
+            let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0),
+                cell_rect(pos), COLOR_WHITE)?;
+            graphics::draw(ctx, &rectangle, graphics::DrawParam::default())?;
 
-        self.rect = graphics::Rect::from(self.position);
+            if let Some(kind) = grid.get(pos) {
+                draw_block(ctx, blocks, pos, kind)?;
+            }
+        }
+    }
 
-        let (dark_color, light_color) = color_for_kind(self.kind);
+    Ok(())
+}
 
-        self.outer_mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(),
-            self.rect, dark_color).unwrap();
+// Only the visible rows are drawn, blocks up in the hidden ones are skipped
+fn draw_piece(ctx: &mut Context, blocks: &BlockMeshes, piece: &Piece) -> GameResult<()> {
+    for &pos in piece.cells().iter().filter(|pos| pos.y >= 0) {
+        draw_block(ctx, blocks, pos, piece.kind())?;
+    }
+    Ok(())
+}
 
-        self.inner_mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(),
-            self.inner_rect(), light_color).unwrap();
+// Outlines where the piece will land, in its own color but faded
+fn draw_ghost(ctx: &mut Context, ghost: &Piece) -> GameResult<()> {
+    let (dark_color, light_color) = color_for_kind(ghost.kind());
+    let fill = Color { a: GHOST_FILL_ALPHA, ..light_color };
+    let outline = Color { a: GHOST_OUTLINE_ALPHA, ..dark_color };
+
+    for &pos in ghost.cells().iter().filter(|pos| pos.y >= 0) {
+        let rect = cell_rect(pos);
+        let inner_rect = graphics::Rect::new(rect.x+2.0, rect.y+2.0, BLOCK_INNER_SIZE, BLOCK_INNER_SIZE);
+        let fill_mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(),
+            inner_rect, fill)?;
+        let outline_mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(3.0),
+            inner_rect, outline)?;
+
+        graphics::draw(ctx, &fill_mesh, graphics::DrawParam::default())?;
+        graphics::draw(ctx, &outline_mesh, graphics::DrawParam::default())?;
     }
+    Ok(())
+}
 
-    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
-        if self.render {
-            graphics::draw(ctx, &self.outer_mesh, graphics::DrawParam::default()).unwrap();
-            graphics::draw(ctx, &self.inner_mesh, graphics::DrawParam::default()).unwrap();
-        }
-        Ok(())
+// Draws a piece in its spawn orientation with its box's top left corner at
+// (x, y), using the preview sized blocks
+fn draw_shape(ctx: &mut Context, blocks: &BlockMeshes, kind: PieceKind, greyed: bool, x: f32, y: f32) -> GameResult<()> {
+    for &(cx, cy) in shape_for(kind, 0).iter() {
+        blocks.draw(ctx, kind, greyed, x + cx as f32 * PREVIEW_BLOCK_SIZE, y + cy as f32 * PREVIEW_BLOCK_SIZE)?;
     }
+    Ok(())
+}
 
-    fn inner_rect(&self) -> graphics::Rect {
-        graphics::Rect::new(self.rect.x+2.0, self.rect.y+2.0, BLOCK_INNER_SIZE, BLOCK_INNER_SIZE)
+fn draw_label(ctx: &mut Context, label: &str, x: f32, y: f32) -> GameResult<()> {
+    let text = graphics::Text::new(graphics::TextFragment::new(label)
+        .scale(graphics::Scale::uniform(24.0)));
+    graphics::draw(ctx, &text, graphics::DrawParam::default().dest([x, y]))
+}
+
+fn draw_next_pieces(ctx: &mut Context, blocks: &BlockMeshes, game: &Game) -> GameResult<()> {
+    let panel_x = panel_pos_x(game.grid());
+    draw_label(ctx, "NEXT", panel_x, GRID_POS_Y)?;
+
+    let mut y = GRID_POS_Y + 40.0;
+    for &kind in game.next_pieces().iter() {
+        draw_shape(ctx, blocks, kind, false, panel_x, y)?;
+        y += PREVIEW_BLOCK_SIZE * 3.0;
     }
+    Ok(())
+}
 
-    fn do_render(&mut self) {
-        self.render = true;
+// The held piece is greyed out while it can't be swapped back in
+fn draw_hold_piece(ctx: &mut Context, blocks: &BlockMeshes, game: &Game) -> GameResult<()> {
+    draw_label(ctx, "HOLD", HOLD_POS_X, GRID_POS_Y)?;
+
+    if let Some(kind) = game.hold_piece() {
+        draw_shape(ctx, blocks, kind, !game.can_hold(), HOLD_POS_X, GRID_POS_Y + 40.0)?;
     }
+    Ok(())
+}
+
+fn draw_small_text(ctx: &mut Context, text: &str, x: f32, y: f32) -> GameResult<()> {
+    let text = graphics::Text::new(graphics::TextFragment::new(text)
+        .scale(graphics::Scale::uniform(20.0)));
+    graphics::draw(ctx, &text, graphics::DrawParam::default().dest([x, y]))
+}
+
+// Score, level, combo and the last clear go under the hold box
+fn draw_score(ctx: &mut Context, game: &Game) -> GameResult<()> {
+    let scoring = game.scoring();
+    let mut y = SCORE_POS_Y;
+
+    draw_label(ctx, "SCORE", HOLD_POS_X, y)?;
+    draw_small_text(ctx, &game.score().to_string(), HOLD_POS_X, y + 30.0)?;
+    y += 70.0;
+
+    draw_small_text(ctx, &format!("Level {}", game.level()), HOLD_POS_X, y)?;
+    draw_small_text(ctx, &format!("Lines {}", game.lines()), HOLD_POS_X, y + 24.0)?;
+    y += 60.0;
 
-    fn set_inactive(&mut self) {
-        self.active = false;
+    if let Some(combo) = scoring.combo().filter(|&combo| combo > 0) {
+        draw_small_text(ctx, &format!("{} Combo", combo), HOLD_POS_X, y)?;
     }
+    y += 30.0;
 
-    fn active_and_render(&mut self) {
-        self.render = true;
-        self.active = true;
+    if let Some(clear) = scoring.last_clear() {
+        if clear.back_to_back {
+            draw_small_text(ctx, "Back-to-Back", HOLD_POS_X, y)?;
+        }
+        draw_small_text(ctx, clear.label, HOLD_POS_X, y + 24.0)?;
     }
 
-    fn deactivate(&mut self) {
-        self.render = false;
-        self.active = false;
+    // T-spins are in the clear's name already, other spins only show here
+    let spin = game.last_lock().and_then(|lock| lock.spin).filter(|spin| spin.piece != PieceKind::T);
+    if let Some(spin) = spin {
+        draw_small_text(ctx, &format!("{:?}-Spin", spin.piece), HOLD_POS_X, y + 48.0)?;
     }
 
-    fn set_offset(&mut self, x: i16, y: i16) {
-        self.offset = GridPosition::from((self.position.x + x, self.position.y + y));
+    if game.last_lock().map_or(false, |lock| lock.perfect_clear) {
+        draw_small_text(ctx, "Perfect Clear", HOLD_POS_X, y + 72.0)?;
     }
+    Ok(())
 }
 
+fn board_rect(grid: &Grid) -> graphics::Rect {
+    graphics::Rect::new(GRID_POS_X, GRID_POS_Y,
+        grid.width() as f32 * GRID_SIZE + 2.0, grid.height() as f32 * GRID_SIZE + 2.0)
+}
 
+// Centers lines of text of the given size and color in the area, starting a
+// third of the way down
+fn draw_centered_lines(ctx: &mut Context, area: graphics::Rect, lines: &[(String, f32, Color)]) -> GameResult<()> {
+    let mut y = area.y + area.h / 3.0;
+    for (line, size, color) in lines.iter() {
+        let text = graphics::Text::new(graphics::TextFragment::new(line.as_str())
+            .scale(graphics::Scale::uniform(*size))
+            .color(*color));
+        let x = area.x + (area.w - text.width(ctx) as f32) / 2.0;
+        graphics::draw(ctx, &text, graphics::DrawParam::default().dest([x, y]))?;
+        y += size + 12.0;
+    }
 
-impl Distribution<PieceKind> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PieceKind { // I J L O S T Z
-        match rng.gen_range(0..7) {
-            0 => PieceKind::I,
-            1 => PieceKind::J,
-            2 => PieceKind::L,
-            3 => PieceKind::O,
-            4 => PieceKind::S,
-            5 => PieceKind::T,
-            _ => PieceKind::Z,
-        }
+    Ok(())
+}
+
+// A heading, some plain lines and then the items with the selected one lit up
+fn draw_menu(ctx: &mut Context, area: graphics::Rect, heading: &str, info: &[String],
+    items: &[String], menu: &Menu) -> GameResult<()>
+{
+    let mut lines = vec![(heading.to_string(), 48.0, COLOR_WHITE)];
+    lines.extend(info.iter().map(|line| (line.clone(), 28.0, COLOR_WHITE)));
+    lines.push((String::new(), 8.0, COLOR_WHITE));
+    for (i, item) in items.iter().enumerate() {
+        let color = if i == menu.selected() { COLOR_YELLOW_LIGHT } else { COLOR_GREY_LIGHT };
+        lines.push((item.clone(), 28.0, color));
     }
+    draw_centered_lines(ctx, area, &lines)
 }
 
-#[derive(Clone, Debug)]
-struct Piece {
-    position: GridPosition,
-    kind: PieceKind,
-    blocks: Vec<Vec<Block>>,
-    active: bool,
-    rotation: i8,
-    num_rotations: i8,
+// Darkens the board so a menu can go on top of it
+fn draw_board_overlay(ctx: &mut Context, grid: &Grid) -> GameResult<()> {
+    let overlay = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(),
+        board_rect(grid), [0.0, 0.0, 0.0, 0.7].into())?;
+    graphics::draw(ctx, &overlay, graphics::DrawParam::default())
 }
 
-impl Piece {
-    fn new(ctx: &mut Context, x: i16, y: i16, kind: PieceKind) -> Piece {
-        let mut p = Piece {
-            position: GridPosition::from((x, y)),
-            kind: kind,
-            blocks: Vec::with_capacity(4),
-            active: true,
-            rotation: 0,
-            num_rotations: 2,
-        };
+// Fixed menu controls, so menus stay usable whatever the game bindings are
+fn menu_input_for_key(keycode: KeyCode) -> Option<MenuInput> {
+    match keycode {
+        KeyCode::Up => Some(MenuInput::Up),
+        KeyCode::Down => Some(MenuInput::Down),
+        KeyCode::Left => Some(MenuInput::Left),
+        KeyCode::Right => Some(MenuInput::Right),
+        KeyCode::Return | KeyCode::Space => Some(MenuInput::Confirm),
+        KeyCode::Escape | KeyCode::Back => Some(MenuInput::Back),
+        _ => None,
+    }
+}
+
+fn menu_input_for_button(button: Button) -> Option<MenuInput> {
+    match button {
+        Button::DPadUp => Some(MenuInput::Up),
+        Button::DPadDown => Some(MenuInput::Down),
+        Button::DPadLeft => Some(MenuInput::Left),
+        Button::DPadRight => Some(MenuInput::Right),
+        Button::South | Button::Start => Some(MenuInput::Confirm),
+        Button::East => Some(MenuInput::Back),
+        _ => None,
+    }
+}
 
-        let row = vec![Block::empty(ctx, kind); 4];
-        for _ in 0..4 {
-            p.blocks.push(row.clone());
+// Sticks steer menus through whatever they're bound to in game
+fn menu_input_for_action(action: Action) -> Option<MenuInput> {
+    match action {
+        Action::MoveLeft => Some(MenuInput::Left),
+        Action::MoveRight => Some(MenuInput::Right),
+        Action::SoftDrop => Some(MenuInput::Down),
+        Action::HardDrop => Some(MenuInput::Up),
+        _ => None,
+    }
+}
+
+fn default_keys() -> Bindings<KeyCode> {
+    Bindings::new(&[
+        (KeyCode::Left, Action::MoveLeft),
+        (KeyCode::Right, Action::MoveRight),
+        (KeyCode::Down, Action::SoftDrop),
+        (KeyCode::Space, Action::HardDrop),
+        (KeyCode::X, Action::RotateCw),
+        (KeyCode::Up, Action::RotateCw),
+        (KeyCode::Z, Action::RotateCcw),
+        (KeyCode::LControl, Action::RotateCcw),
+        (KeyCode::A, Action::Rotate180),
+        (KeyCode::C, Action::Hold),
+        (KeyCode::LShift, Action::Hold),
+        (KeyCode::Escape, Action::Pause),
+        (KeyCode::P, Action::Pause),
+        (KeyCode::R, Action::Restart),
+        (KeyCode::G, Action::ToggleGhost),
+    ])
+}
+
+fn default_gamepad() -> Gamepad<Button, Axis> {
+    let buttons = Bindings::new(&[
+        (Button::DPadLeft, Action::MoveLeft),
+        (Button::DPadRight, Action::MoveRight),
+        (Button::DPadDown, Action::SoftDrop),
+        (Button::DPadUp, Action::HardDrop),
+        (Button::South, Action::RotateCw),
+        (Button::East, Action::RotateCcw),
+        (Button::North, Action::Rotate180),
+        (Button::LeftTrigger, Action::Hold),
+        (Button::RightTrigger, Action::Hold),
+        (Button::Start, Action::Pause),
+        (Button::Select, Action::Restart),
+    ]);
+    let axes = Bindings::new(&[
+        ((Axis::LeftStickX, AxisDirection::Negative), Action::MoveLeft),
+        ((Axis::LeftStickX, AxisDirection::Positive), Action::MoveRight),
+        ((Axis::LeftStickY, AxisDirection::Negative), Action::SoftDrop),
+    ]);
+    Gamepad::new(buttons, axes, DEFAULT_THRESHOLD)
+}
+
+// Matches a name against the listed variants of an enum
+macro_rules! from_name {
+    ($name:expr, $type:ident: $($variant:ident),*) => {
+        match $name {
+            $(stringify!($variant) => Some($type::$variant),)*
+            _ => None,
         }
+    };
+}
 
-        p.num_rotations = match kind {
-            PieceKind::O => 1,
-            PieceKind::I | PieceKind::S | PieceKind::Z => 2,
-            PieceKind::J | PieceKind::L | PieceKind::T => 4,
-        };
+// Keys in the controls file go by their KeyCode names, e.g. "Left" or "LShift"
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    from_name!(name, KeyCode: Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+        Insert, Home, Delete, End, PageDown, PageUp, Left, Up, Right, Down, Back, Return, Space,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        NumpadEnter, Add, Subtract, Multiply, Divide, Decimal,
+        Apostrophe, Backslash, Comma, Equals, Grave, LBracket, RBracket, Minus, Period, Semicolon, Slash,
+        Tab, LAlt, LControl, LShift, RAlt, RControl, RShift)
+}
+
+fn button_from_name(name: &str) -> Option<Button> {
+    from_name!(name, Button: South, East, North, West, C, Z, LeftTrigger, LeftTrigger2,
+        RightTrigger, RightTrigger2, Select, Start, Mode, LeftThumb, RightThumb,
+        DPadUp, DPadDown, DPadLeft, DPadRight)
+}
+
+fn axis_from_name(name: &str) -> Option<Axis> {
+    from_name!(name, Axis: LeftStickX, LeftStickY, LeftZ, RightStickX, RightStickY, RightZ, DPadX, DPadY)
+}
 
-        p.set_rotation(0i8);
+// A missing file means the defaults, a broken part of one is reported and
+// that part falls back to the defaults
+fn load_controls(ctx: &Context) -> (Bindings<KeyCode>, Gamepad<Button, Axis>) {
+    let path = filesystem::user_config_dir(ctx).join(CONTROLS_FILE);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return (default_keys(), default_gamepad()),
+        Err(err) => {
+            eprintln!("{}: {}, using the default controls", path.display(), err);
+            return (default_keys(), default_gamepad());
+        },
+    };
+
+    let keys = Bindings::load(&default_keys(), &text, "keys", key_from_name).unwrap_or_else(|err| {
+        eprintln!("{}: {}, using the default keys", path.display(), err);
+        default_keys()
+    });
+    let gamepad = Gamepad::load(&default_gamepad(), &text, button_from_name, axis_from_name)
+        .unwrap_or_else(|err| {
+            eprintln!("{}: {}, using the default gamepad controls", path.display(), err);
+            default_gamepad()
+        });
+    (keys, gamepad)
+}
+
+// Command line options, e.g. `rustycubes --mode classic --seed 42`
+struct Options {
+    mode: Mode,
+    // Overrides the mode's generator
+    generator: Option<GeneratorKind>,
+    seed: u64,
+    // Ruleset flags as given, applied on top of whichever mode is picked
+    rule_flags: Vec<(String, String)>,
+    show_ghost: bool,
+    // A replay file to watch instead of playing
+    replay: Option<String>,
+}
 
-        for r in 0..4 {
-            for c in 0..4 {
-                p.blocks[r][c].set_offset(r as i16, c as i16);
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let mut options = Options {
+            mode: Mode::default(),
+            generator: None,
+            seed: rand::random(),
+            rule_flags: Vec::new(),
+            show_ghost: true,
+            replay: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--mode" => options.mode = value()?.parse()?,
+                "--generator" => options.generator = Some(value()?.parse()?),
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = seed.parse().map_err(|_| format!("invalid seed '{}'", seed))?;
+                },
+                flag if VALUE_FLAGS.contains(&flag) => {
+                    let value = value()?;
+                    options.rule_flags.push((arg, value));
+                },
+                flag if SWITCH_FLAGS.contains(&flag) => options.rule_flags.push((arg, String::new())),
+                "--no-ghost" => options.show_ghost = false,
+                "--replay" => options.replay = Some(value()?),
+                _ => return Err(format!("unknown argument '{}'", arg)),
             }
         }
-        
-        p
+
+        // Bad values should stop us now rather than when a game starts
+        options.rules(options.mode)?;
+        Ok(options)
+    }
+
+    fn rules(&self, mode: Mode) -> Result<Ruleset, String> {
+        let mut rules = mode.ruleset();
+        for (flag, value) in self.rule_flags.iter() {
+            rules.apply_flag(flag, value)?;
+        }
+        Ok(rules)
     }
 
-    fn new_random(ctx: &mut Context) -> Piece {
-        let kind: PieceKind = rand::random();
-        let p = Piece::new(ctx, 4, 0, kind);
-        p
+    fn generator(&self, mode: Mode) -> GeneratorKind {
+        self.generator.unwrap_or_else(|| mode.generator())
     }
+}
 
-    fn update_fast(&mut self, ctx: &mut Context) {
-        for r in 0..4 {
-            for c in 0..4 {
-                self.blocks[r][c].update(ctx, self.position);
+// Player preferences from the settings menu, they win over the mode
+struct Settings {
+    show_ghost: bool,
+    das: u32,
+    arr: u32,
+}
 
-                if self.blocks[r][c].position.y + 1 == GRID_COLS as i16 {
-                    self.active = false;
-                }
-            }
+const MAX_DAS: u32 = 30;
+const MAX_ARR: u32 = 10;
+
+// Screens stacked on top of each other, only the top one gets input
+enum Scene {
+    Title(Menu),
+    Settings(Menu),
+    Playing,
+    Paused(Menu),
+    GameOver(Menu),
+    Replay(Box<Viewer>),
+}
+
+// A replay being watched. It can be paused, stepped a frame at a time while
+// paused and fast-forwarded while held.
+struct Viewer {
+    playback: Playback,
+    paused: bool,
+    fast_forward: bool,
+    // Once the replay is over, whether the game ended up where the recording did
+    result: Option<Result<(), String>>,
+}
+
+impl Viewer {
+    fn new(replay: Replay) -> Viewer {
+        Viewer {
+            playback: Playback::new(replay),
+            paused: false,
+            fast_forward: false,
+            result: None,
         }
     }
 
-    fn update(&mut self, ctx: &mut Context, grid: &mut Grid) {
-        if !self.active{ return; }
-
-        self.position.y += 1;
-        for r in 0..4 {
-            for c in 0..4 {
-                let b = &mut self.blocks[r][c];
-                if !b.active { continue; }
+    fn step(&mut self, game: &mut Game) {
+        if self.result.is_some() || self.playback.step(game) {
+            return;
+        }
 
-                b.update(ctx, self.position);
-                if b.position.y + 1 == GRID_COLS as i16 {
-                    self.active = false;
-                }
-                
-                if b.position.x < 10 && b.position.y < 19 && grid.cells[b.position.x as usize][b.position.y as usize + 1].occupied {
-                    println!("OCCUPIED");
-                    self.active = false;
-                }
-            }
+        let result = self.playback.check(game);
+        if let Err(err) = &result {
+            eprintln!("{}", err);
         }
+        self.result = Some(result);
+    }
+}
 
-        // Piece is now dead
-        if !self.active {
-            for r in 0..4 {
-                for c in 0..4 {
-                    if !self.blocks[r][c].render {continue;}
+const TITLE_START: usize = 0;
+const TITLE_MODE: usize = 1;
+const TITLE_SETTINGS: usize = 2;
+const TITLE_QUIT: usize = 3;
 
-                    let mut pos = self.blocks[r][c].position;
-                    println!("Dying at {}, {}", pos.x, pos.y);
-                    pos.x = if pos.x >= GRID_ROWS as i16 { GRID_ROWS as i16 - 1 } else { pos.x };
-                    pos.y = if pos.y >= GRID_COLS as i16 { GRID_COLS as i16 - 1 } else { pos.y };
-                    println!("Actualized to {}, {}", pos.x, pos.y);
+const SETTINGS_GHOST: usize = 0;
+const SETTINGS_DAS: usize = 1;
+const SETTINGS_ARR: usize = 2;
+const SETTINGS_BACK: usize = 3;
 
-                    grid.cells[pos.x as usize][pos.y as usize].set_block(ctx, &self.blocks[r][c]);
-                }
-            }
-        }
+const PAUSED_RESUME: usize = 0;
+const PAUSED_RESTART: usize = 1;
+const PAUSED_QUIT: usize = 2;
+
+const GAME_OVER_AGAIN: usize = 0;
+const GAME_OVER_TITLE: usize = 1;
+
+fn title_case(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
     }
+}
 
-    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
-        for r in 0..4 {
-            for c in 0..4 {
-                self.blocks[r][c].draw(ctx)?;
-            }
-        }
-        Ok(())
+// Turns wall-clock time into frames, feeds them and key presses into the
+// Game and draws it, with menus stacked on top
+struct State {
+    clock: FrameClock,
+    options: Options,
+    // The seed for the first game, later ones get random seeds
+    first_seed: Option<u64>,
+    mode: Mode,
+    settings: Settings,
+    game: Game,
+    keys: Bindings<KeyCode>,
+    gamepad: Gamepad<Button, Axis>,
+    scenes: Vec<Scene>,
+    replay_dir: std::path::PathBuf,
+    board_blocks: BlockMeshes,
+    preview_blocks: BlockMeshes,
+    // Why the last game couldn't start, shown on the title screen
+    error: Option<String>,
+    // The game being played, saved to the replay directory when it ends
+    recording: Option<Replay>,
+}
+
+impl State {
+    pub fn new(ctx: &mut Context, options: Options, keys: Bindings<KeyCode>, gamepad: Gamepad<Button, Axis>)
+        -> GameResult<State>
+    {
+        let mode = options.mode;
+        let rules = options.rules(mode).map_err(GameError::ConfigError)?;
+        let settings = Settings {
+            show_ghost: options.show_ghost,
+            das: rules.das,
+            arr: rules.arr,
+        };
+        let game = Game::new(rules, options.generator(mode).build(options.seed));
+
+        Ok(State {
+            clock: FrameClock::default(),
+            first_seed: Some(options.seed),
+            options,
+            mode,
+            settings,
+            game,
+            keys,
+            gamepad,
+            scenes: vec![Scene::Title(Menu::new(4))],
+            replay_dir: filesystem::user_data_dir(ctx).join(REPLAY_DIR),
+            board_blocks: BlockMeshes::new(ctx, GRID_SIZE)?,
+            preview_blocks: BlockMeshes::new(ctx, PREVIEW_BLOCK_SIZE)?,
+            error: None,
+            recording: None,
+        })
     }
 
-    fn move_left(&mut self) {
-        self.position.x -= 1;
+    // Rules that don't work out for the picked mode leave us on the title
+    // screen with the reason shown
+    fn start_game(&mut self) {
+        self.save_recording();
+
+        let mut rules = match self.options.rules(self.mode) {
+            Ok(rules) => rules,
+            Err(err) => {
+                self.error = Some(err);
+                self.scenes.truncate(1);
+                return;
+            },
+        };
+        self.error = None;
+
+        let seed = self.first_seed.take().unwrap_or_else(rand::random);
+        rules.das = self.settings.das;
+        rules.arr = self.settings.arr;
+        let generator = self.options.generator(self.mode);
+
+        self.game = Game::new(rules, generator.build(seed));
+        self.recording = Some(Replay::new(self.game.rules().clone(), generator, seed));
+        self.scenes = vec![Scene::Title(Menu::new(4)), Scene::Playing];
     }
 
-    fn move_right(&mut self) {
-        self.position.x += 1;
+    // Writes out the game being recorded, named after when it ended
+    fn save_recording(&mut self) {
+        let mut recording = match self.recording.take() {
+            Some(recording) => recording,
+            None => return,
+        };
+        recording.finish(&self.game);
+
+        let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let path = self.replay_dir.join(format!("{}-{}.replay", time, recording.seed));
+        match std::fs::create_dir_all(&self.replay_dir).and_then(|_| std::fs::write(&path, recording.to_string())) {
+            Ok(()) => println!("Replay saved to {}", path.display()),
+            Err(err) => eprintln!("{}: {}", path.display(), err),
+        }
     }
 
-    fn deactivate_all_blocks(&mut self) {
-        for r in 0..4 {
-            for c in 0..4 {
-                self.blocks[r][c].deactivate();
+    fn watch(&mut self, replay: Replay) {
+        self.game = replay.start();
+        self.scenes = vec![Scene::Replay(Box::new(Viewer::new(replay)))];
+    }
+
+    // Everything that reaches the game goes through here, so it's recorded
+    fn apply(&mut self, event: ActionEvent) {
+        if replay::apply(&mut self.game, event) {
+            if let Some(recording) = &mut self.recording {
+                recording.record(self.game.frame(), event);
             }
         }
     }
 
-    fn set_rotation(&mut self, rotation: i8) {
-        println!("Rotation: {}", rotation);
-        self.rotation = rotation;
-        self.deactivate_all_blocks();
-        match self.kind {
-            PieceKind::I => {
-                match rotation {
-                    0 => { // Horizontal ------
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[3][1].active_and_render();
-                    },
-                    1 => { // Vertical
-                        self.blocks[2][0].active_and_render();
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[2][2].active_and_render();
-                        self.blocks[2][3].active_and_render();
-                    },
-                    _ => (),
-                };
-            },
-            PieceKind::J => {
-                match rotation {
-                    0 => {
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[0][2].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                        self.blocks[2][2].active_and_render();
-                    },
-                    1 => {
-                        self.blocks[1][0].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                        self.blocks[2][2].active_and_render();
-                    },
-                    2 => {
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[0][2].active_and_render();
-                    },
-                    3 => {
-                        self.blocks[0][0].active_and_render();
-                        self.blocks[1][0].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    _ => (),
-                }
-            },
-            PieceKind::L => {
-                match rotation {
-                    0 => {
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[0][2].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                        self.blocks[2][2].active_and_render();
-                    },
-                    1 => {
-                        self.blocks[1][0].active_and_render();
-                        self.blocks[2][0].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    2 => {
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[2][2].active_and_render();
-                    },
-                    3 => {
-                        self.blocks[1][0].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[0][2].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    _ => (),
-                }
-            },
-            PieceKind::O => { // L, O, S, T, Z
-                match rotation {
-                    0 => {
-                        self.blocks[0][0].active_and_render();
-                        self.blocks[1][0].active_and_render();
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                    },
-                    _ => (),
-                }
-            },
-            PieceKind::S => {
-                match rotation {
-                    0 => {
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[0][2].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    1 => {
-                        self.blocks[0][0].active_and_render();
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    _ => (),
-                }
-            },
-            PieceKind::T => {
-                match rotation {
-                    0 => {
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[0][2].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                        self.blocks[2][2].active_and_render();
-                    },
-                    1 => {
-                        self.blocks[1][0].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    2 => {
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    3 => {
-                        self.blocks[1][0].active_and_render();
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    _ => (),
-                }
-            },
-            PieceKind::Z => {
-                match rotation {
-                    0 => {
-                        self.blocks[0][1].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                        self.blocks[2][2].active_and_render();
-                    },
-                    1 => {
-                        self.blocks[2][0].active_and_render();
-                        self.blocks[1][1].active_and_render();
-                        self.blocks[2][1].active_and_render();
-                        self.blocks[1][2].active_and_render();
-                    },
-                    _ => (),
-                }
-            },
-            _ => ()
-        } // end match self.kind
+    // Held directions and soft drop would stay stuck if their release came
+    // in while a menu was up
+    fn release_held(&mut self) {
+        self.apply(ActionEvent::Release(Action::MoveLeft));
+        self.apply(ActionEvent::Release(Action::MoveRight));
+        self.apply(ActionEvent::Release(Action::SoftDrop));
     }
 
-    fn activate_next_rotation(&mut self) {
-        self.set_rotation(modulo(self.rotation+1, self.num_rotations));
+    fn pause(&mut self) {
+        self.release_held();
+        self.scenes.push(Scene::Paused(Menu::new(3)));
     }
 
-    fn activate_prev_rotation(&mut self) {
-        println!("Current rotation: {}", self.rotation);
-        self.set_rotation(modulo(self.rotation-1, self.num_rotations));
+    fn press(&mut self, action: Action) {
+        match action {
+            Action::Pause => self.pause(),
+            Action::Restart => self.start_game(),
+            Action::ToggleGhost => self.settings.show_ghost = !self.settings.show_ghost,
+            _ => self.apply(ActionEvent::Press(action)),
+        }
     }
 
-}
-
-#[derive(Debug, Clone)]
-struct GridCell {
-    position: GridPosition,
-    rect: graphics::Rect,
-    occupied: bool,
-    block: Option<Block>
-}
+    fn release(&mut self, action: Action) {
+        self.apply(ActionEvent::Release(action));
+    }
 
-impl GridCell {
-    fn new() -> GridCell {
-        GridCell {
-            position: (0, 0).into(),
-            rect: graphics::Rect::zero(),
-            occupied: false,
-            block: None
+    // Bound actions drive the game while playing. In menus the pause action
+    // still resumes and sticks can move the cursor.
+    fn handle(&mut self, ctx: &mut Context, event: ActionEvent) {
+        match (self.scenes.last(), event) {
+            (Some(Scene::Playing), ActionEvent::Press(action)) => self.press(action),
+            (Some(Scene::Playing), ActionEvent::Release(action)) => self.release(action),
+            (Some(Scene::Paused(_)), ActionEvent::Press(Action::Pause)) => { self.scenes.pop(); },
+            (_, ActionEvent::Press(action)) => {
+                if let Some(input) = menu_input_for_action(action) {
+                    self.menu_input(ctx, input);
+                }
+            },
+            (_, ActionEvent::Release(_)) => (),
         }
     }
 
-    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
-        let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0),
-            self.rect, COLOR_WHITE).unwrap();
-
-        graphics::draw(ctx, &rectangle, graphics::DrawParam::default()).unwrap();
+    fn menu_input(&mut self, ctx: &mut Context, input: MenuInput) {
+        let event = match self.scenes.last_mut() {
+            Some(Scene::Title(menu)) | Some(Scene::Settings(menu))
+                | Some(Scene::Paused(menu)) | Some(Scene::GameOver(menu)) => menu.handle(input),
+            Some(Scene::Playing) | Some(Scene::Replay(_)) | None => return,
+        };
 
-        if let Some(block) = &self.block {
-            block.draw(ctx)?;
+        match self.scenes.last() {
+            Some(Scene::Title(_)) => self.title_event(ctx, event),
+            Some(Scene::Settings(_)) => self.settings_event(event),
+            Some(Scene::Paused(_)) => self.paused_event(event),
+            Some(Scene::GameOver(_)) => self.game_over_event(event),
+            _ => (),
         }
-
-        Ok(())
     }
 
-    fn set_position(&mut self, x: i16, y: i16) {
-        self.position = (x, y).into();
-        self.rect = self.position.into();
+    fn cycle_mode(&mut self, step: i32) {
+        let count = Mode::ALL.len() as i32;
+        let index = Mode::ALL.iter().position(|&mode| mode == self.mode).unwrap_or(0) as i32;
+        self.mode = Mode::ALL[(index + step).rem_euclid(count) as usize];
     }
 
-    fn set_block(&mut self, ctx: &mut Context, block: &Block) {
-        println!("Setting block with x: {}, y: {}", block.position.x, block.position.y);
-        self.occupied = true;
-        self.block = Some(Block::new(ctx, block.position.x, block.position.y, block.kind));
-        if let Some(block) = &mut self.block {
-            block.set_inactive();
-            block.do_render();
+    fn title_event(&mut self, ctx: &mut Context, event: MenuEvent) {
+        match event {
+            MenuEvent::Chosen(TITLE_START) => self.start_game(),
+            MenuEvent::Chosen(TITLE_MODE) => self.cycle_mode(1),
+            MenuEvent::Adjusted(TITLE_MODE, step) => self.cycle_mode(step),
+            MenuEvent::Chosen(TITLE_SETTINGS) => self.scenes.push(Scene::Settings(Menu::new(4))),
+            MenuEvent::Chosen(TITLE_QUIT) => ggez::event::quit(ctx),
+            _ => (),
         }
     }
-}
 
-struct Grid {
-    x: f32,
-    y: f32,
-    cells: Vec<Vec<GridCell>>
-}
+    fn settings_event(&mut self, event: MenuEvent) {
+        let adjust = |value: u32, step: i32, max: u32| (value as i32 + step).clamp(0, max as i32) as u32;
+        match event {
+            MenuEvent::Chosen(SETTINGS_GHOST) | MenuEvent::Adjusted(SETTINGS_GHOST, _) =>
+                self.settings.show_ghost = !self.settings.show_ghost,
+            MenuEvent::Adjusted(SETTINGS_DAS, step) => self.settings.das = adjust(self.settings.das, step, MAX_DAS),
+            MenuEvent::Adjusted(SETTINGS_ARR, step) => self.settings.arr = adjust(self.settings.arr, step, MAX_ARR),
+            MenuEvent::Chosen(SETTINGS_BACK) | MenuEvent::Back => { self.scenes.pop(); },
+            _ => (),
+        }
+    }
 
-impl Grid {
-    pub fn new(x: f32, y: f32) -> Grid {
-        let mut grid = Grid {
-            x: x,
-            y: y,
-            cells: Vec::with_capacity(GRID_ROWS),
-        };
+    fn paused_event(&mut self, event: MenuEvent) {
+        match event {
+            MenuEvent::Chosen(PAUSED_RESUME) | MenuEvent::Back => { self.scenes.pop(); },
+            MenuEvent::Chosen(PAUSED_RESTART) => self.start_game(),
+            MenuEvent::Chosen(PAUSED_QUIT) => {
+                self.save_recording();
+                self.scenes.truncate(1);
+            },
+            _ => (),
+        }
+    }
 
-        let row = vec![GridCell::new(); GRID_COLS];
-        for _ in 0..GRID_ROWS {
-            grid.cells.push(row.clone());
+    fn game_over_event(&mut self, event: MenuEvent) {
+        match event {
+            MenuEvent::Chosen(GAME_OVER_AGAIN) => self.start_game(),
+            MenuEvent::Chosen(GAME_OVER_TITLE) | MenuEvent::Back => self.scenes.truncate(1),
+            _ => (),
         }
+    }
 
-        for r in 0..GRID_ROWS {
-            for c in 0..GRID_COLS {
-                grid.cells[r][c].set_position(r as i16, c as i16);
-            }
+    fn draw_game(&self, ctx: &mut Context, hidden: bool) -> GameResult<()> {
+        // A paused game hides the board so pausing can't be used to plan ahead
+        if hidden {
+            return draw_grid(ctx, &self.board_blocks, &Grid::new(self.game.grid().width(), self.game.grid().height(), 0));
         }
 
-        grid
-    }
+        draw_grid(ctx, &self.board_blocks, self.game.grid())?;
+        draw_next_pieces(ctx, &self.preview_blocks, &self.game)?;
+        draw_hold_piece(ctx, &self.preview_blocks, &self.game)?;
+        draw_score(ctx, &self.game)?;
 
-    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
-        for r in 0..GRID_ROWS {
-            for c in 0..GRID_COLS {
-                self.cells[r][c].draw(ctx).unwrap();
+        if self.settings.show_ghost {
+            if let Some(ghost) = self.game.ghost_piece() {
+                draw_ghost(ctx, &ghost)?;
             }
         }
 
+        if let Some(piece) = self.game.piece() {
+            draw_piece(ctx, &self.board_blocks, piece)?;
+        }
+
         Ok(())
     }
-}
 
-struct State {
-    dt: std::time::Duration,
-    last_update: Instant,
-    piece: Option<Piece>,
-    grid: Grid,
-}
+    fn draw_title(&self, ctx: &mut Context, menu: &Menu) -> GameResult<()> {
+        let screen = graphics::screen_coordinates(ctx);
+        let info: Vec<String> = self.error.iter().cloned().collect();
+        draw_menu(ctx, screen, "RUSTYCUBES", &info, &[
+            "Start".to_string(),
+            format!("< Mode: {} >", title_case(self.mode.name())),
+            "Settings".to_string(),
+            "Quit".to_string(),
+        ], menu)
+    }
 
-impl State {
-    pub fn new(ctx: &mut Context) -> State {
-        // Initialization code here
-        State {
-            dt: std::time::Duration::new(0, 0),
-            last_update: Instant::now(),
-            piece: Some(Piece::new_random(ctx)),
-            grid: Grid::new(GRID_POS_X, GRID_POS_Y),
-        }
+    fn draw_settings(&self, ctx: &mut Context, menu: &Menu) -> GameResult<()> {
+        let screen = graphics::screen_coordinates(ctx);
+        draw_menu(ctx, screen, "SETTINGS", &[], &[
+            format!("< Ghost: {} >", if self.settings.show_ghost { "On" } else { "Off" }),
+            format!("< DAS: {} frames >", self.settings.das),
+            format!("< ARR: {} frames >", self.settings.arr),
+            "Back".to_string(),
+        ], menu)
     }
 
-    
-}
+    fn draw_paused(&self, ctx: &mut Context, menu: &Menu) -> GameResult<()> {
+        draw_board_overlay(ctx, self.game.grid())?;
+        draw_menu(ctx, board_rect(self.game.grid()), "PAUSED", &[], &[
+            "Resume".to_string(),
+            "Restart".to_string(),
+            "Quit to title".to_string(),
+        ], menu)
+    }
 
-fn can_move(piece: &Piece, grid: &Grid, direction: Direction) -> bool {
-    println!("=====================================================");
-    if direction == Direction::RIGHT && (piece.position.x >= GRID_ROWS as i16 - 1) {
-        return false;
-    } else if direction == Direction::LEFT && piece.position.x <= 0 {
-        return false;
+    fn draw_game_over(&self, ctx: &mut Context, menu: &Menu) -> GameResult<()> {
+        draw_board_overlay(ctx, self.game.grid())?;
+        draw_menu(ctx, board_rect(self.game.grid()), "GAME OVER", &[
+            format!("Score: {}", self.game.score()),
+            format!("Level: {}", self.game.level()),
+            format!("Lines: {}", self.game.lines()),
+            format!("Pieces: {}", self.game.pieces()),
+            format!("Perfect clears: {}", self.game.perfect_clears()),
+        ], &[
+            "Play again".to_string(),
+            "Title".to_string(),
+        ], menu)
     }
-    
-    for r in 0..4 {
-        for c in 0..4 {
-            if !piece.blocks[r][c].active { continue; }
-            let pos = piece.blocks[r][c].position;
-            println!("[x: {}, y: {}]", pos.x, pos.y);
-            let py = pos.y as usize;
-            if direction == Direction::LEFT {
-                if pos.x == 0 {
-                    println!("px is 0 !");
-                    return false
-                }
-                let px = (pos.x - 1) as usize;
-                if grid.cells[px][py].occupied {
-                    println!("{} is occupied!", px);
-                    return false
-                }
-            } else {
-                let px = (pos.x + 1) as usize;
-                if px == 10 || grid.cells[px][py].occupied {
-                    return false
-                }
-            }
+
+    // The frame count and viewer state go under the board, with how it
+    // ended on top once it's over
+    fn draw_replay(&self, ctx: &mut Context, viewer: &Viewer) -> GameResult<()> {
+        let rect = board_rect(self.game.grid());
+        let mut status = format!("REPLAY  frame {} / {}", self.game.frame(), viewer.playback.replay().frames);
+        if viewer.paused {
+            status.push_str("  paused");
+        } else if viewer.fast_forward {
+            status.push_str(&format!("  x{}", FAST_FORWARD));
+        }
+        draw_small_text(ctx, &status, rect.x, rect.bottom() + 12.0)?;
+        draw_small_text(ctx, "P pause  . step  F fast-forward  R restart  Esc quit", rect.x, rect.bottom() + 36.0)?;
+
+        let (heading, info) = match &viewer.result {
+            None => return Ok(()),
+            Some(Ok(())) => ("REPLAY OVER", "Matches the recording"),
+            Some(Err(_)) => ("DESYNC", "Doesn't match the recording"),
+        };
+        draw_board_overlay(ctx, self.game.grid())?;
+        draw_menu(ctx, rect, heading, &[
+            info.to_string(),
+            format!("Score: {}", self.game.score()),
+            format!("Lines: {}", self.game.lines()),
+            format!("Pieces: {}", self.game.pieces()),
+        ], &[], &Menu::new(0))
+    }
+
+    fn replay_key(&mut self, ctx: &mut Context, keycode: KeyCode, down: bool) {
+        let viewer = match self.scenes.last_mut() {
+            Some(Scene::Replay(viewer)) => viewer,
+            _ => return,
+        };
+
+        match keycode {
+            KeyCode::F | KeyCode::Tab => viewer.fast_forward = down,
+            _ if !down => (),
+            KeyCode::P | KeyCode::Space => viewer.paused = !viewer.paused,
+            KeyCode::Period | KeyCode::Right if viewer.paused => viewer.step(&mut self.game),
+            KeyCode::R => {
+                let replay = viewer.playback.replay().clone();
+                self.watch(replay);
+            },
+            KeyCode::Escape => ggez::event::quit(ctx),
+            _ => (),
         }
     }
-    println!("=====================================================");
-    true
+
+    // The gamepad gets the same viewer controls as the keyboard
+    fn replay_button(&mut self, ctx: &mut Context, button: Button, down: bool) {
+        let keycode = match button {
+            Button::RightTrigger | Button::RightTrigger2 => KeyCode::F,
+            Button::Start => KeyCode::P,
+            Button::DPadRight => KeyCode::Period,
+            Button::Select => KeyCode::R,
+            Button::East => KeyCode::Escape,
+            _ => return,
+        };
+        self.replay_key(ctx, keycode, down);
+    }
 }
 
 impl ggez::event::EventHandler for State {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        self.dt = timer::delta(ctx);
-
-        if Instant::now() - self.last_update >= Duration::from_millis(MILLIS_PER_UPDATE) {
-            if let Some(piece) = &mut self.piece {
-                piece.update(ctx, &mut self.grid);
-
-                if !piece.active {
-                    self.piece = Some(Piece::new_random(ctx));
-                }
+        // The game only ever sees whole frames, however uneven the real ones are
+        let frames = self.clock.advance(timer::delta(ctx));
+        if let Some(Scene::Playing) = self.scenes.last() {
+            for _ in 0..frames {
+                self.game.tick();
             }
-            
 
-            self.last_update = Instant::now();
-        } else {
-            if let Some(piece) = &mut self.piece {
-                piece.update_fast(ctx);
+            if self.game.phase() == GamePhase::GameOver {
+                self.release_held();
+                self.save_recording();
+                self.scenes.push(Scene::GameOver(Menu::new(2)));
+            }
+        } else if let Some(Scene::Replay(viewer)) = self.scenes.last_mut() {
+            let frames = match (viewer.paused, viewer.fast_forward) {
+                (true, _) => 0,
+                (false, true) => frames * FAST_FORWARD,
+                (false, false) => frames,
+            };
+            for _ in 0..frames {
+                viewer.step(&mut self.game);
             }
         }
-        
+
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx, [0.1, 0.1, 0.1, 1.0].into());
-
-        self.grid.draw(ctx)?;
-
-        if let Some(piece) = &self.piece {
-            piece.draw(ctx)?;
+        fit_screen(ctx, self.game.grid())?;
+
+        match self.scenes.last() {
+            Some(Scene::Title(menu)) => self.draw_title(ctx, menu)?,
+            Some(Scene::Settings(menu)) => self.draw_settings(ctx, menu)?,
+            Some(Scene::Playing) => self.draw_game(ctx, false)?,
+            Some(Scene::Paused(menu)) => {
+                self.draw_game(ctx, true)?;
+                self.draw_paused(ctx, menu)?;
+            },
+            Some(Scene::GameOver(menu)) => {
+                self.draw_game(ctx, false)?;
+                self.draw_game_over(ctx, menu)?;
+            },
+            Some(Scene::Replay(viewer)) => {
+                self.draw_game(ctx, false)?;
+                self.draw_replay(ctx, viewer)?;
+            },
+            None => (),
         }
-        
+
         graphics::present(ctx)?;
         Ok(())
   }
 
-  fn key_down_event(&mut self, ctx: &mut Context, keycode: ggez::event::KeyCode, _keymods: ggez::event::KeyMods, _repeat: bool) {
-    match keycode {
-        ggez::event::KeyCode::Right => {
-            if let Some(piece) = &mut self.piece {
-                if can_move(&piece, &self.grid, Direction::RIGHT) {
-                    piece.move_right();
-                }
-            }
-        },
-        ggez::event::KeyCode::Left => {
-            if let Some(piece) = &mut self.piece {
-                if can_move(&piece, &self.grid, Direction::LEFT) {
-                    piece.move_left();
-                }
-            }
-        },
-        ggez::event::KeyCode::Z => {
-            if let Some(piece) = &mut self.piece {
-                piece.activate_prev_rotation();
-            }
-        },
-        ggez::event::KeyCode::X => {
-            if let Some(piece) = &mut self.piece {
-                piece.activate_next_rotation();
-            }
+  fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, _keymods: ggez::event::KeyMods, repeat: bool) {
+    if let Some(Scene::Replay(_)) = self.scenes.last() {
+        self.replay_key(ctx, keycode, true);
+    } else if let Some(Scene::Playing) = self.scenes.last() {
+        // The game does its own auto repeat, the OS one would only get in the way
+        if repeat {
+            return;
+        }
+
+        if let Some(action) = self.keys.action(&keycode) {
+            self.press(action);
+        }
+    } else if !repeat {
+        // Only fresh presses, or a key still held from the game would pick
+        // a menu entry as soon as the menu opens
+        if let Some(input) = menu_input_for_key(keycode) {
+            self.menu_input(ctx, input);
+        } else if let Some(action) = self.keys.action(&keycode) {
+            self.handle(ctx, ActionEvent::Press(action));
+        }
+    }
+  }
+
+  fn key_up_event(&mut self, ctx: &mut Context, keycode: KeyCode, _keymods: ggez::event::KeyMods) {
+    if let Some(Scene::Replay(_)) = self.scenes.last() {
+        self.replay_key(ctx, keycode, false);
+    } else if let Some(action) = self.keys.action(&keycode) {
+        self.release(action);
+    }
+  }
+
+  // Every connected gamepad drives the same game
+  fn gamepad_button_down_event(&mut self, ctx: &mut Context, button: Button, _id: GamepadId) {
+    let menu_input = match self.scenes.last() {
+        Some(Scene::Replay(_)) => return self.replay_button(ctx, button, true),
+        Some(Scene::Playing) => None,
+        _ => menu_input_for_button(button),
+    };
+
+    match menu_input {
+        Some(input) => self.menu_input(ctx, input),
+        None => if let Some(event) = self.gamepad.button_down(&button) {
+            self.handle(ctx, event);
         },
-        _ => {}
     }
   }
 
-}
+  fn gamepad_button_up_event(&mut self, ctx: &mut Context, button: Button, _id: GamepadId) {
+    if let Some(Scene::Replay(_)) = self.scenes.last() {
+        self.replay_button(ctx, button, false);
+    } else if let Some(event) = self.gamepad.button_up(&button) {
+        self.handle(ctx, event);
+    }
+  }
+
+  fn gamepad_axis_event(&mut self, ctx: &mut Context, axis: Axis, value: f32, _id: GamepadId) {
+    for event in self.gamepad.axis(axis, value) {
+        self.handle(ctx, event);
+    }
+  }
 
-fn modulo(x: i8, m: i8) -> i8 {
-    (x % m + m) % m
 }
 
 fn main() {
     println!("Hello, world!");
 
+    let options = Options::from_args().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        eprintln!("usage: rustycubes [--mode marathon|classic|master] [--generator bag|uniform|history] [--seed <number>] [--width <4-40>] [--height <4-80>] [--hidden-rows <0-40>] [--preview <1-6>] [--gravity guideline|nes|<cells per frame,...>] [--lines-per-level <number>] [--lock-delay <frames>] [--lock-resets <number>] [--das <frames>] [--arr <frames>] [--no-das-carry] [--all-spin] [--no-ghost] [--replay <file>]");
+        std::process::exit(1);
+    });
+    // A replay that can't be read is better reported before the window opens
+    let replay = options.replay.as_ref().map(|path| {
+        std::fs::read_to_string(path).map_err(|err| err.to_string())
+            .and_then(|text| text.parse::<Replay>())
+            .unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            })
+    });
+    match &replay {
+        Some(replay) => println!("Seed: {}", replay.seed),
+        None => println!("Seed: {}", options.seed),
+    }
+
     let c = conf::Conf::new();
     
     let (ref mut ctx, ref mut event_loop) = ContextBuilder::new("rustycubes", "Dylan Socolobsky")
@@ -748,7 +1088,14 @@ fn main() {
     ggez::graphics::set_screen_coordinates(ctx, ggez::graphics::Rect::new(0.0, 0.0, WINDOW_WIDTH, WINDOW_HEIGHT))
         .unwrap();
 
-    let state = &mut State::new(ctx);
+    let (keys, gamepad) = load_controls(ctx);
+    let state = &mut State::new(ctx, options, keys, gamepad).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    });
+    if let Some(replay) = replay {
+        state.watch(replay);
+    }
 
     event::run(ctx, event_loop, state).unwrap();
 }
@@ -0,0 +1,82 @@
+// Menu navigation without any drawing. The frontend owns the labels and
+// decides what choosing an item does, this only tracks the cursor.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuEvent {
+    // The cursor moved or nothing happened
+    None,
+    Chosen(usize),
+    // Left or right on an item, for cycling through its values
+    Adjusted(usize, i32),
+    Back,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Menu {
+    len: usize,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn new(len: usize) -> Menu {
+        Menu { len: len.max(1), selected: 0 }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // The cursor wraps around at both ends
+    pub fn handle(&mut self, input: MenuInput) -> MenuEvent {
+        match input {
+            MenuInput::Up => {
+                self.selected = (self.selected + self.len - 1) % self.len;
+                MenuEvent::None
+            },
+            MenuInput::Down => {
+                self.selected = (self.selected + 1) % self.len;
+                MenuEvent::None
+            },
+            MenuInput::Left => MenuEvent::Adjusted(self.selected, -1),
+            MenuInput::Right => MenuEvent::Adjusted(self.selected, 1),
+            MenuInput::Confirm => MenuEvent::Chosen(self.selected),
+            MenuInput::Back => MenuEvent::Back,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_wraps_around() {
+        let mut menu = Menu::new(3);
+        assert_eq!(menu.handle(MenuInput::Up), MenuEvent::None);
+        assert_eq!(menu.selected(), 2);
+        menu.handle(MenuInput::Down);
+        assert_eq!(menu.selected(), 0);
+        menu.handle(MenuInput::Down);
+        assert_eq!(menu.handle(MenuInput::Confirm), MenuEvent::Chosen(1));
+    }
+
+    #[test]
+    fn sideways_adjusts_the_selected_item() {
+        let mut menu = Menu::new(4);
+        menu.handle(MenuInput::Down);
+        menu.handle(MenuInput::Down);
+        assert_eq!(menu.handle(MenuInput::Left), MenuEvent::Adjusted(2, -1));
+        assert_eq!(menu.handle(MenuInput::Right), MenuEvent::Adjusted(2, 1));
+        assert_eq!(menu.handle(MenuInput::Back), MenuEvent::Back);
+    }
+}
@@ -0,0 +1,495 @@
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+
+use crate::grid::Grid;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GridPosition {
+    pub x: i16,
+    pub y: i16
+}
+
+impl GridPosition {
+    pub fn new(x: i16, y: i16) -> GridPosition {
+        GridPosition { x, y }
+    }
+}
+
+impl From<(i16, i16)> for GridPosition {
+    fn from(pos: (i16, i16)) -> Self {
+        GridPosition::new(pos.0, pos.1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PieceKind {
+    I,
+    J,
+    L,
+    O,
+    S,
+    T,
+    Z,
+}
+
+impl PieceKind {
+    pub const ALL: [PieceKind; 7] = [PieceKind::I, PieceKind::J, PieceKind::L,
+        PieceKind::O, PieceKind::S, PieceKind::T, PieceKind::Z];
+}
+
+impl Distribution<PieceKind> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PieceKind { // I J L O S T Z
+        match rng.gen_range(0..7) {
+            0 => PieceKind::I,
+            1 => PieceKind::J,
+            2 => PieceKind::L,
+            3 => PieceKind::O,
+            4 => PieceKind::S,
+            5 => PieceKind::T,
+            _ => PieceKind::Z,
+        }
+    }
+}
+
+// Every piece has four rotation states: 0 (spawn), R, 2 and L
+pub const ROTATION_STATES: i8 = 4;
+
+// The (x, y) cells a piece covers inside its 4x4 box, with y pointing down
+pub type Shape = [(i16, i16); 4];
+
+// Shapes for every rotation state of each piece, indexed by PieceKind
+pub type ShapeTable = [[Shape; ROTATION_STATES as usize]; 7];
+
+// The SRS rotation states, except that J and L are mirrored: the J here has
+// the corner block on the right like the SRS L, and the other way round. The
+// original game drew them that way and the kicks are the same for both.
+pub const SRS_SHAPES: ShapeTable = [
+    // I
+    [
+        [(0, 1), (1, 1), (2, 1), (3, 1)],
+        [(2, 0), (2, 1), (2, 2), (2, 3)],
+        [(0, 2), (1, 2), (2, 2), (3, 2)],
+        [(1, 0), (1, 1), (1, 2), (1, 3)],
+    ],
+    // J
+    [
+        [(2, 0), (0, 1), (1, 1), (2, 1)],
+        [(1, 0), (1, 1), (1, 2), (2, 2)],
+        [(0, 1), (1, 1), (2, 1), (0, 2)],
+        [(0, 0), (1, 0), (1, 1), (1, 2)],
+    ],
+    // L
+    [
+        [(0, 0), (0, 1), (1, 1), (2, 1)],
+        [(1, 0), (2, 0), (1, 1), (1, 2)],
+        [(0, 1), (1, 1), (2, 1), (2, 2)],
+        [(1, 0), (1, 1), (0, 2), (1, 2)],
+    ],
+    // O
+    [
+        [(0, 0), (1, 0), (0, 1), (1, 1)],
+        [(0, 0), (1, 0), (0, 1), (1, 1)],
+        [(0, 0), (1, 0), (0, 1), (1, 1)],
+        [(0, 0), (1, 0), (0, 1), (1, 1)],
+    ],
+    // S
+    [
+        [(1, 0), (2, 0), (0, 1), (1, 1)],
+        [(1, 0), (1, 1), (2, 1), (2, 2)],
+        [(1, 1), (2, 1), (0, 2), (1, 2)],
+        [(0, 0), (0, 1), (1, 1), (1, 2)],
+    ],
+    // T
+    [
+        [(1, 0), (0, 1), (1, 1), (2, 1)],
+        [(1, 0), (1, 1), (2, 1), (1, 2)],
+        [(0, 1), (1, 1), (2, 1), (1, 2)],
+        [(1, 0), (0, 1), (1, 1), (1, 2)],
+    ],
+    // Z
+    [
+        [(0, 0), (1, 0), (1, 1), (2, 1)],
+        [(2, 0), (1, 1), (2, 1), (1, 2)],
+        [(0, 1), (1, 1), (1, 2), (2, 2)],
+        [(1, 0), (0, 1), (1, 1), (0, 2)],
+    ],
+];
+
+pub fn shape_for(kind: PieceKind, rotation: i8) -> &'static Shape {
+    &SRS_SHAPES[kind as usize][rotation as usize]
+}
+
+// Super Rotation System wall kicks, as (x, y) offsets with y pointing up like in the
+// published tables. Each row is tried in order until the rotated piece fits.
+// Rows are 0->R, R->0, R->2, 2->R, 2->L, L->2, L->0, 0->L
+const JLSTZ_KICKS: [[(i16, i16); 5]; 8] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+];
+
+const I_KICKS: [[(i16, i16); 5]; 8] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+];
+
+fn kicks_for(kind: PieceKind, from: i8, to: i8) -> &'static [(i16, i16)] {
+    let row = match (from, to) {
+        (0, 1) => 0,
+        (1, 0) => 1,
+        (1, 2) => 2,
+        (2, 1) => 3,
+        (2, 3) => 4,
+        (3, 2) => 5,
+        (3, 0) => 6,
+        (0, 3) => 7,
+        _ => return &[(0, 0)],
+    };
+
+    match kind {
+        PieceKind::I => &I_KICKS[row],
+        PieceKind::O => &[(0, 0)],
+        PieceKind::J | PieceKind::L | PieceKind::S | PieceKind::T | PieceKind::Z => &JLSTZ_KICKS[row],
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Piece {
+    position: GridPosition,
+    kind: PieceKind,
+    rotation: i8,
+}
+
+impl Piece {
+    pub fn new(x: i16, y: i16, kind: PieceKind) -> Piece {
+        Piece {
+            position: GridPosition::from((x, y)),
+            kind,
+            rotation: 0,
+        }
+    }
+
+    // A fresh piece ready to fall, in the two hidden rows right above the
+    // visible ones when the grid has them. It goes just left of the middle,
+    // or as far as it can on grids too narrow for that. Like the guideline,
+    // it then drops a row straight away if there's room, so it's in view
+    // from its first frame.
+    pub fn spawn(kind: PieceKind, grid: &Grid) -> Piece {
+        let shape_width = shape_for(kind, 0).iter().map(|&(x, _)| x + 1).max().unwrap_or(0);
+        let x = (grid.width() as i16 / 2 - 1).min(grid.width() as i16 - shape_width).max(0);
+        let mut piece = Piece::new(x, grid.top().max(-2), kind);
+
+        if piece.is_above_visible_area() {
+            piece.move_down();
+            if !grid.fits(&piece) {
+                piece.move_up();
+            }
+        }
+        piece
+    }
+
+    pub fn kind(&self) -> PieceKind {
+        self.kind
+    }
+
+    pub fn position(&self) -> GridPosition {
+        self.position
+    }
+
+    pub fn rotation(&self) -> i8 {
+        self.rotation
+    }
+
+    // Grid positions of the blocks that make up the current shape
+    pub fn cells(&self) -> [GridPosition; 4] {
+        let shape = shape_for(self.kind, self.rotation);
+        let mut cells = [self.position; 4];
+        for (cell, &(x, y)) in cells.iter_mut().zip(shape.iter()) {
+            cell.x += x;
+            cell.y += y;
+        }
+        cells
+    }
+
+    // True when the piece sits entirely above the visible playfield
+    pub fn is_above_visible_area(&self) -> bool {
+        self.cells().iter().all(|pos| pos.y < 0)
+    }
+
+    pub fn move_left(&mut self) {
+        self.position.x -= 1;
+    }
+
+    pub fn move_right(&mut self) {
+        self.position.x += 1;
+    }
+
+    pub fn move_down(&mut self) {
+        self.position.y += 1;
+    }
+
+    pub fn move_up(&mut self) {
+        self.position.y -= 1;
+    }
+
+    pub fn set_rotation(&mut self, rotation: i8) {
+        self.rotation = rotation;
+    }
+
+    // Rotates into the given state, trying each wall kick in turn, and
+    // returns the index of the kick that fit. Leaves the piece untouched and
+    // returns None if none of them do.
+    pub fn rotate_to(&mut self, grid: &Grid, rotation: i8) -> Option<usize> {
+        let mut rotated = self.clone();
+        rotated.set_rotation(rotation);
+
+        for (kick, &(x, y)) in kicks_for(self.kind, self.rotation, rotation).iter().enumerate() {
+            rotated.position = (self.position.x + x, self.position.y - y).into();
+            if grid.fits(&rotated) {
+                *self = rotated;
+                return Some(kick);
+            }
+        }
+
+        None
+    }
+
+    // Rotates by a number of quarter turns clockwise, negative for
+    // counterclockwise. SRS has no kicks for half turns, so those only
+    // rotate in place.
+    pub fn turn(&mut self, grid: &Grid, quarter_turns: i8) -> Option<usize> {
+        self.rotate_to(grid, modulo(self.rotation + quarter_turns, ROTATION_STATES))
+    }
+}
+
+pub fn modulo(x: i8, m: i8) -> i8 {
+    (x % m + m) % m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::DEFAULT_HEIGHT;
+
+    fn sorted_cells(piece: &Piece) -> Vec<(i16, i16)> {
+        let mut cells: Vec<(i16, i16)> = piece.cells().iter().map(|pos| (pos.x, pos.y)).collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    // Normalizes a shape so comparisons ignore where it sits inside the box
+    fn normalized(cells: &[(i16, i16)]) -> Vec<(i16, i16)> {
+        let min_x = cells.iter().map(|c| c.0).min().unwrap();
+        let min_y = cells.iter().map(|c| c.1).min().unwrap();
+        let mut cells: Vec<(i16, i16)> = cells.iter().map(|c| (c.0 - min_x, c.1 - min_y)).collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    // The states set_rotation drew just before the table replaced it. Those
+    // already differ from the very first shapes, some of which sat a row
+    // lower or a column further left until the SRS kicks needed SRS positions.
+    #[test]
+    fn shape_table_reproduces_the_pre_table_shapes() {
+        let pre_table: [(PieceKind, i8, Shape); 16] = [
+            (PieceKind::I, 0, [(0, 1), (1, 1), (2, 1), (3, 1)]),
+            (PieceKind::I, 1, [(2, 0), (2, 1), (2, 2), (2, 3)]),
+            (PieceKind::J, 0, [(2, 0), (0, 1), (1, 1), (2, 1)]),
+            (PieceKind::J, 1, [(1, 0), (1, 1), (1, 2), (2, 2)]),
+            (PieceKind::J, 2, [(0, 1), (1, 1), (2, 1), (0, 2)]),
+            (PieceKind::J, 3, [(0, 0), (1, 0), (1, 1), (1, 2)]),
+            (PieceKind::L, 0, [(0, 0), (0, 1), (1, 1), (2, 1)]),
+            (PieceKind::L, 1, [(1, 0), (2, 0), (1, 1), (1, 2)]),
+            (PieceKind::L, 2, [(0, 1), (1, 1), (2, 1), (2, 2)]),
+            (PieceKind::L, 3, [(1, 0), (1, 1), (0, 2), (1, 2)]),
+            (PieceKind::O, 0, [(0, 0), (1, 0), (0, 1), (1, 1)]),
+            (PieceKind::S, 0, [(1, 0), (2, 0), (0, 1), (1, 1)]),
+            (PieceKind::S, 1, [(1, 0), (1, 1), (2, 1), (2, 2)]),
+            (PieceKind::T, 0, [(1, 0), (0, 1), (1, 1), (2, 1)]),
+            (PieceKind::Z, 0, [(0, 0), (1, 0), (1, 1), (2, 1)]),
+            (PieceKind::Z, 1, [(2, 0), (1, 1), (2, 1), (1, 2)]),
+        ];
+
+        for (kind, rotation, cells) in pre_table.iter() {
+            let mut piece = Piece::new(0, 0, *kind);
+            piece.set_rotation(*rotation);
+
+            let mut expected = cells.to_vec();
+            expected.sort_unstable();
+            assert_eq!(sorted_cells(&piece), expected, "{:?} rotation {}", kind, rotation);
+        }
+    }
+
+    #[test]
+    fn every_state_is_four_cells_inside_the_box() {
+        for &kind in PieceKind::ALL.iter() {
+            for rotation in 0..ROTATION_STATES {
+                let shape = shape_for(kind, rotation);
+                let mut cells = shape.to_vec();
+                cells.sort_unstable();
+                cells.dedup();
+
+                assert_eq!(cells.len(), 4, "{:?} rotation {}", kind, rotation);
+                assert!(cells.iter().all(|&(x, y)| (0..4).contains(&x) && (0..4).contains(&y)));
+            }
+        }
+    }
+
+    #[test]
+    fn each_state_is_the_clockwise_turn_of_the_previous_one() {
+        for &kind in PieceKind::ALL.iter() {
+            for rotation in 0..ROTATION_STATES {
+                let next = modulo(rotation + 1, ROTATION_STATES);
+                let turned: Vec<(i16, i16)> = shape_for(kind, rotation).iter()
+                    .map(|&(x, y)| (-y, x))
+                    .collect();
+
+                assert_eq!(normalized(&turned), normalized(shape_for(kind, next)),
+                    "{:?} rotation {} -> {}", kind, rotation, next);
+            }
+        }
+    }
+
+    #[test]
+    fn rotating_four_times_returns_to_spawn() {
+        let grid = Grid::default();
+        for &kind in PieceKind::ALL.iter() {
+            let mut piece = Piece::new(4, 5, kind);
+            let spawn = sorted_cells(&piece);
+
+            for _ in 0..ROTATION_STATES {
+                assert!(piece.turn(&grid, 1).is_some());
+            }
+            assert_eq!(piece.rotation, 0);
+            assert_eq!(sorted_cells(&piece), spawn, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn rotates_in_place_when_there_is_room() {
+        let grid = Grid::default();
+        let mut piece = Piece::new(4, 5, PieceKind::T);
+
+        assert!(piece.turn(&grid, 1).is_some());
+        assert_eq!(piece.rotation, 1);
+        assert_eq!(piece.position, GridPosition::from((4, 5)));
+    }
+
+    #[test]
+    fn t_spin_triple_uses_the_last_kick() {
+        // Mirrored TST: the T rests on the left with an overhang above its right arm
+        let mut grid = Grid::default();
+        grid.set((2, 15).into(), Some(PieceKind::O));
+        grid.fill_line_except(17, 2);
+        grid.fill_line_except(18, 1);
+        grid.set((2, 18).into(), None);
+        grid.fill_line_except(19, 2);
+
+        let mut piece = Piece::new(0, 15, PieceKind::T);
+        assert!(grid.fits(&piece));
+
+        assert_eq!(piece.turn(&grid, -1), Some(4));
+        assert_eq!(piece.rotation, 3);
+        assert_eq!(piece.position, GridPosition::from((1, 17)));
+        assert_eq!(sorted_cells(&piece), vec![(1, 18), (2, 17), (2, 18), (2, 19)]);
+
+        grid.lock(&piece);
+        assert_eq!(grid.clear_lines(), 3);
+    }
+
+    #[test]
+    fn i_piece_kicks_off_the_left_wall() {
+        let grid = Grid::default();
+        let mut piece = Piece::new(-2, 10, PieceKind::I);
+        piece.set_rotation(1);
+        assert!(grid.fits(&piece));
+
+        assert!(piece.turn(&grid, -1).is_some());
+        assert_eq!(piece.rotation, 0);
+        assert_eq!(piece.position, GridPosition::from((0, 10)));
+        assert_eq!(sorted_cells(&piece), vec![(0, 11), (1, 11), (2, 11), (3, 11)]);
+    }
+
+    #[test]
+    fn i_piece_kicks_off_the_right_wall() {
+        let grid = Grid::default();
+        let mut piece = Piece::new(8, 10, PieceKind::I);
+        piece.set_rotation(3);
+        assert!(grid.fits(&piece));
+
+        assert!(piece.turn(&grid, 1).is_some());
+        assert_eq!(piece.rotation, 0);
+        assert_eq!(piece.position, GridPosition::from((6, 10)));
+        assert_eq!(sorted_cells(&piece), vec![(6, 11), (7, 11), (8, 11), (9, 11)]);
+    }
+
+    #[test]
+    fn rotation_is_rejected_when_every_kick_fails() {
+        let mut grid = Grid::default();
+        grid.fill_line_except(DEFAULT_HEIGHT as i16 - 2, -1);
+        grid.fill_line_except(DEFAULT_HEIGHT as i16 - 1, -1);
+        for x in 0..4 {
+            grid.set((x, DEFAULT_HEIGHT as i16 - 1).into(), None);
+        }
+
+        let mut piece = Piece::new(0, DEFAULT_HEIGHT as i16 - 2, PieceKind::I);
+        assert!(grid.fits(&piece));
+
+        assert_eq!(piece.turn(&grid, 1), None);
+        assert_eq!(piece.turn(&grid, -1), None);
+        assert_eq!(piece.rotation, 0);
+        assert_eq!(piece.position, GridPosition::from((0, DEFAULT_HEIGHT as i16 - 2)));
+    }
+
+    #[test]
+    fn spawns_in_the_middle_of_any_width() {
+        let spawn_x = |kind, width| Piece::spawn(kind, &Grid::new(width, 20, 0)).position.x;
+
+        assert_eq!(spawn_x(PieceKind::T, 10), 4);
+        assert_eq!(spawn_x(PieceKind::I, 10), 4);
+        assert_eq!(spawn_x(PieceKind::T, 20), 9);
+        assert_eq!(spawn_x(PieceKind::O, 4), 1);
+        assert_eq!(spawn_x(PieceKind::I, 4), 0);
+
+        for &width in [4, 5, 10, 20].iter() {
+            let grid = Grid::new(width, 20, 0);
+            for &kind in PieceKind::ALL.iter() {
+                assert!(grid.fits(&Piece::spawn(kind, &grid)), "{:?} on {} wide", kind, width);
+            }
+        }
+    }
+
+    #[test]
+    fn spawns_at_the_top_of_the_visible_rows() {
+        for &(hidden, top) in [(20, -1), (1, -1), (0, 0)].iter() {
+            let grid = Grid::new(10, 20, hidden);
+            let piece = Piece::spawn(PieceKind::T, &grid);
+            assert_eq!(piece.position.y, top);
+            assert!(grid.fits(&piece));
+        }
+        for &kind in PieceKind::ALL.iter() {
+            let piece = Piece::spawn(kind, &Grid::default());
+            assert!(piece.cells().iter().any(|pos| pos.y >= 0), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn stays_in_the_hidden_rows_when_the_top_row_is_taken() {
+        let mut grid = Grid::default();
+        grid.fill_line_except(0, 0);
+        let piece = Piece::spawn(PieceKind::T, &grid);
+        assert_eq!(piece.position.y, -2);
+        assert!(piece.is_above_visible_area());
+    }
+}
@@ -0,0 +1,421 @@
+// Recording games and playing them back. A replay only stores what the game
+// can't work out for itself: the rules, the generator and its seed, and every
+// input with the frame it happened on. Playing those back through a fresh
+// Game gives the same game, frame for frame.
+//
+// The file is plain text, a versioned header, the rules one per line and then
+// the inputs, each as the frames since the one before and the action:
+//
+//     rustycubes-replay 1
+//     generator bag
+//     seed 42
+//     ...
+//     events
+//     12 +move_left
+//     9 -move_left
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::controls::Action;
+use crate::game::{Game, GamePhase, Input};
+use crate::gamepad::ActionEvent;
+use crate::generator::GeneratorKind;
+use crate::grid::Grid;
+use crate::rules::Ruleset;
+use crate::shift::Direction;
+
+pub const REPLAY_VERSION: u32 = 1;
+
+const HEADER: &str = "rustycubes-replay";
+
+// Feeds an action into the game, the same way for live play and playback.
+// Returns false for pause, restart and the ghost toggle, which are up to
+// whoever runs the game.
+pub fn apply(game: &mut Game, event: ActionEvent) -> bool {
+    match event {
+        ActionEvent::Press(action) => match action {
+            Action::MoveLeft => game.press_shift(Direction::Left),
+            Action::MoveRight => game.press_shift(Direction::Right),
+            Action::SoftDrop => game.set_soft_drop(true),
+            Action::HardDrop => { game.input(Input::HardDrop); },
+            Action::RotateCw => { game.input(Input::RotateCw); },
+            Action::RotateCcw => { game.input(Input::RotateCcw); },
+            Action::Rotate180 => { game.input(Input::Rotate180); },
+            Action::Hold => { game.input(Input::Hold); },
+            Action::Pause | Action::Restart | Action::ToggleGhost => return false,
+        },
+        ActionEvent::Release(action) => match action {
+            Action::MoveLeft => game.release_shift(Direction::Left),
+            Action::MoveRight => game.release_shift(Direction::Right),
+            Action::SoftDrop => game.set_soft_drop(false),
+            Action::Pause | Action::Restart | Action::ToggleGhost => return false,
+            _ => (),
+        },
+    }
+    true
+}
+
+// FNV-1a over the size and every cell, hidden rows included
+pub fn board_checksum(grid: &Grid) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |byte: u8| {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    };
+
+    for &size in [grid.width(), grid.height(), grid.hidden_rows()].iter() {
+        size.to_le_bytes().iter().for_each(|&byte| add(byte));
+    }
+    for x in 0..grid.width() as i16 {
+        for y in grid.top()..grid.height() as i16 {
+            add(grid.get((x, y).into()).map_or(0, |kind| kind as u8 + 1));
+        }
+    }
+    hash
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replay {
+    // Scoring isn't stored, replays always use the guideline table
+    pub rules: Ruleset,
+    pub generator: GeneratorKind,
+    pub seed: u64,
+    // Inputs in the order they happened, with the frame each one came in on
+    pub events: Vec<(u64, ActionEvent)>,
+    // How many frames the game ran and the checksum of the board it ended
+    // with, filled in by finish
+    pub frames: u64,
+    pub checksum: u64,
+}
+
+impl Replay {
+    pub fn new(rules: Ruleset, generator: GeneratorKind, seed: u64) -> Replay {
+        Replay {
+            rules,
+            generator,
+            seed,
+            events: Vec::new(),
+            frames: 0,
+            checksum: 0,
+        }
+    }
+
+    // A new game set up the way the recorded one started
+    pub fn start(&self) -> Game {
+        Game::new(self.rules.clone(), self.generator.build(self.seed))
+    }
+
+    pub fn record(&mut self, frame: u64, event: ActionEvent) {
+        self.events.push((frame, event));
+    }
+
+    // Notes where the recorded game ended up, for playback to check against
+    pub fn finish(&mut self, game: &Game) {
+        self.frames = game.frame();
+        self.checksum = board_checksum(game.grid());
+    }
+}
+
+fn event_name(event: ActionEvent) -> String {
+    match event {
+        ActionEvent::Press(action) => format!("+{}", action),
+        ActionEvent::Release(action) => format!("-{}", action),
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rules = &self.rules;
+        writeln!(f, "{} {}", HEADER, REPLAY_VERSION)?;
+        writeln!(f, "generator {}", self.generator)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "width {}", rules.width)?;
+        writeln!(f, "height {}", rules.height)?;
+        writeln!(f, "hidden_rows {}", rules.hidden_rows)?;
+        writeln!(f, "preview {}", rules.preview)?;
+        writeln!(f, "lines_per_level {}", rules.lines_per_level)?;
+        writeln!(f, "gravity {}", rules.gravity)?;
+        writeln!(f, "lock_delay {}", rules.lock_delay)?;
+        writeln!(f, "lock_resets {}", rules.lock_resets)?;
+        writeln!(f, "das {}", rules.das)?;
+        writeln!(f, "arr {}", rules.arr)?;
+        writeln!(f, "das_carry {}", rules.das_carry)?;
+        writeln!(f, "all_spin {}", rules.all_spin)?;
+        writeln!(f, "frames {}", self.frames)?;
+        writeln!(f, "checksum {:016x}", self.checksum)?;
+
+        writeln!(f, "events")?;
+        let mut last_frame = 0;
+        for &(frame, event) in self.events.iter() {
+            writeln!(f, "{} {}", frame - last_frame, event_name(event))?;
+            last_frame = frame;
+        }
+        Ok(())
+    }
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid {} '{}'", key, value))
+}
+
+fn parse_event(line: &str) -> Result<(u64, ActionEvent), String> {
+    let mut parts = line.split_whitespace();
+    let (delta, event) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(delta), Some(event), None) => (delta, event),
+        _ => return Err(format!("expected frames and an action, not '{}'", line)),
+    };
+
+    let delta = parse_value("frame count", delta)?;
+    let event = match (event.get(..1), event.get(1..)) {
+        (Some("+"), Some(action)) => ActionEvent::Press(action.parse()?),
+        (Some("-"), Some(action)) => ActionEvent::Release(action.parse()?),
+        _ => return Err(format!("expected +action or -action, not '{}'", event)),
+    };
+    Ok((delta, event))
+}
+
+impl FromStr for Replay {
+    type Err = String;
+
+    // Errors say which line is wrong
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+
+        match lines.next().map(|(_, line)| line.split_whitespace().collect::<Vec<_>>()) {
+            Some(ref header) if header.len() == 2 && header[0] == HEADER => {
+                let version: u32 = parse_value("version", header[1])?;
+                if version != REPLAY_VERSION {
+                    return Err(format!("unsupported replay version {}, expected {}", version, REPLAY_VERSION));
+                }
+            },
+            _ => return Err("not a replay file".to_string()),
+        }
+
+        let mut replay = Replay::new(Ruleset::default(), GeneratorKind::default(), 0);
+        let (mut seed, mut frames, mut checksum) = (None, None, None);
+        for (number, line) in &mut lines {
+            if line == "events" {
+                break;
+            }
+
+            let at_line = |err: String| format!("line {}: {}", number, err);
+            let (key, value) = line.split_once(' ').ok_or_else(|| at_line(format!("expected a key and a value, not '{}'", line)))?;
+            let value = value.trim();
+            let rules = &mut replay.rules;
+            match key {
+                "generator" => replay.generator = value.parse().map_err(at_line)?,
+                "seed" => seed = Some(parse_value(key, value).map_err(at_line)?),
+                "width" => rules.width = parse_value(key, value).map_err(at_line)?,
+                "height" => rules.height = parse_value(key, value).map_err(at_line)?,
+                "hidden_rows" => rules.hidden_rows = parse_value(key, value).map_err(at_line)?,
+                "preview" => rules.preview = parse_value(key, value).map_err(at_line)?,
+                "lines_per_level" => rules.lines_per_level = parse_value(key, value).map_err(at_line)?,
+                "gravity" => rules.gravity = value.parse().map_err(at_line)?,
+                "lock_delay" => rules.lock_delay = parse_value(key, value).map_err(at_line)?,
+                "lock_resets" => rules.lock_resets = parse_value(key, value).map_err(at_line)?,
+                "das" => rules.das = parse_value(key, value).map_err(at_line)?,
+                "arr" => rules.arr = parse_value(key, value).map_err(at_line)?,
+                "das_carry" => rules.das_carry = parse_value(key, value).map_err(at_line)?,
+                "all_spin" => rules.all_spin = parse_value(key, value).map_err(at_line)?,
+                "frames" => frames = Some(parse_value(key, value).map_err(at_line)?),
+                "checksum" => checksum = Some(u64::from_str_radix(value, 16)
+                    .map_err(|_| at_line(format!("invalid checksum '{}'", value)))?),
+                _ => return Err(at_line(format!("unknown key '{}'", key))),
+            }
+        }
+
+        let missing = |key| format!("missing {}", key);
+        replay.seed = seed.ok_or_else(|| missing("seed"))?;
+        replay.frames = frames.ok_or_else(|| missing("frames"))?;
+        replay.checksum = checksum.ok_or_else(|| missing("checksum"))?;
+
+        replay.events = parse_event_lines(lines)?;
+        Ok(replay)
+    }
+}
+
+fn parse_event_lines<'a>(lines: impl Iterator<Item = (usize, &'a str)>) -> Result<Vec<(u64, ActionEvent)>, String> {
+    let mut events = Vec::new();
+    let mut frame: u64 = 0;
+    for (number, line) in lines.filter(|(_, line)| !line.is_empty()) {
+        let at_line = |err| format!("line {}: {}", number, err);
+        let (delta, event) = parse_event(line).map_err(at_line)?;
+        frame = frame.checked_add(delta).ok_or_else(|| at_line("too many frames".to_string()))?;
+        events.push((frame, event));
+    }
+    Ok(events)
+}
+
+// Reads inputs written like the events in a replay file, without the rest
+// of it. Lines starting with # are comments.
+pub fn parse_events(text: &str) -> Result<Vec<(u64, ActionEvent)>, String> {
+    let lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+    parse_event_lines(lines.filter(|(_, line)| !line.starts_with('#')))
+}
+
+// Steps a game through a replay's inputs one frame at a time
+#[derive(Clone, Debug)]
+pub struct Playback {
+    replay: Replay,
+    // Index of the first event that hasn't been played yet
+    next: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Playback {
+        Playback { replay, next: 0 }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    // Whether the game has reached the end of the recording, or ended early
+    // because it desynced into a game over
+    pub fn is_finished(&self, game: &Game) -> bool {
+        game.frame() >= self.replay.frames || game.phase() == GamePhase::GameOver
+    }
+
+    // Plays the inputs for the game's current frame and then the frame
+    // itself. Returns false once the recording is over.
+    pub fn step(&mut self, game: &mut Game) -> bool {
+        while let Some(&(frame, event)) = self.replay.events.get(self.next) {
+            if frame > game.frame() {
+                break;
+            }
+            apply(game, event);
+            self.next += 1;
+        }
+
+        if self.is_finished(game) {
+            return false;
+        }
+        game.tick();
+        true
+    }
+
+    // Once finished, whether the game ended up where the recording did
+    pub fn check(&self, game: &Game) -> Result<(), String> {
+        if game.frame() != self.replay.frames {
+            return Err(format!("replay desynced: the game ended on frame {}, the recording on frame {}",
+                game.frame(), self.replay.frames));
+        }
+
+        let checksum = board_checksum(game.grid());
+        if checksum != self.replay.checksum {
+            return Err(format!("replay desynced: the board checksum is {:016x}, the recording has {:016x}",
+                checksum, self.replay.checksum));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plays a fixed pattern of inputs and records them as it goes, the way
+    // the frontend does
+    fn record_game(rules: Ruleset, seed: u64) -> (Replay, Game) {
+        let mut replay = Replay::new(rules.clone(), GeneratorKind::Bag, seed);
+        let mut game = replay.start();
+
+        for frame in 0..3000u64 {
+            // A new piece every 40 frames, spread across the board by
+            // tapping or holding left and right
+            let (piece, phase) = (frame / 40, frame % 40);
+            let direction = if piece % 2 == 0 { Action::MoveLeft } else { Action::MoveRight };
+            let event = match phase {
+                2 if piece % 3 == 0 => Some(ActionEvent::Press(Action::RotateCw)),
+                4 if piece % 5 != 2 => Some(ActionEvent::Press(direction)),
+                5 if piece % 5 == 1 => Some(ActionEvent::Release(direction)),
+                20 => Some(ActionEvent::Release(direction)),
+                35 => Some(ActionEvent::Press(Action::HardDrop)),
+                _ => None,
+            };
+            if let Some(event) = event {
+                apply(&mut game, event);
+                replay.record(game.frame(), event);
+            }
+            if game.phase() == GamePhase::GameOver {
+                break;
+            }
+            game.tick();
+        }
+
+        replay.finish(&game);
+        (replay, game)
+    }
+
+    fn play(replay: &Replay) -> (Playback, Game) {
+        let mut playback = Playback::new(replay.clone());
+        let mut game = replay.start();
+        while playback.step(&mut game) {}
+        (playback, game)
+    }
+
+    #[test]
+    fn playback_reproduces_the_game() {
+        let (replay, recorded) = record_game(Ruleset::default(), 11);
+        assert!(recorded.pieces() > 20);
+
+        let (playback, played) = play(&replay);
+        assert_eq!(playback.check(&played), Ok(()));
+        assert_eq!(played.grid(), recorded.grid());
+        assert_eq!(played.score(), recorded.score());
+        assert_eq!(played.frame(), recorded.frame());
+    }
+
+    #[test]
+    fn file_round_trips() {
+        let rules = Ruleset { width: 8, gravity: "0.1,0.25".parse().unwrap(), arr: 0, all_spin: true, ..Ruleset::default() };
+        let (replay, _) = record_game(rules, 3);
+
+        let text = replay.to_string();
+        assert!(text.starts_with("rustycubes-replay 1\n"));
+        assert_eq!(text.parse::<Replay>(), Ok(replay));
+    }
+
+    #[test]
+    fn tampered_replays_desync() {
+        let (mut replay, _) = record_game(Ruleset::default(), 11);
+        replay.events.retain(|&(_, event)| event != ActionEvent::Press(Action::RotateCw));
+
+        let (playback, played) = play(&replay);
+        assert!(playback.check(&played).unwrap_err().starts_with("replay desynced"));
+
+        let (mut replay, _) = record_game(Ruleset::default(), 11);
+        replay.seed = 12;
+        let (playback, played) = play(&replay);
+        assert!(playback.check(&played).is_err());
+    }
+
+    #[test]
+    fn bad_files_are_rejected() {
+        let (replay, _) = record_game(Ruleset::default(), 1);
+        let text = replay.to_string();
+
+        assert_eq!("hello".parse::<Replay>(), Err("not a replay file".to_string()));
+        assert_eq!(text.replacen("rustycubes-replay 1", "rustycubes-replay 2", 1).parse::<Replay>(),
+            Err("unsupported replay version 2, expected 1".to_string()));
+        assert_eq!(text.replacen("das 10", "das ten", 1).parse::<Replay>(),
+            Err("line 12: invalid das 'ten'".to_string()));
+        assert_eq!(format!("{}4 +jump\n", text).parse::<Replay>().unwrap_err(),
+            format!("line {}: unknown action 'jump'", text.lines().count() + 1));
+        assert_eq!(text.replacen("seed 1\n", "", 1).parse::<Replay>(), Err("missing seed".to_string()));
+    }
+
+    #[test]
+    fn scripts_are_bare_events() {
+        let script = "# slide left and drop\n2 +move_left\n12 -move_left\n\n0 +hard_drop\n";
+        assert_eq!(parse_events(script), Ok(vec![
+            (2, ActionEvent::Press(Action::MoveLeft)),
+            (14, ActionEvent::Release(Action::MoveLeft)),
+            (14, ActionEvent::Press(Action::HardDrop)),
+        ]));
+        assert_eq!(parse_events("1 +move_left\nsoon +hold\n"), Err("line 2: invalid frame count 'soon'".to_string()));
+        assert_eq!(parse_events(&format!("1 +move_left\n{} +hold\n", u64::MAX)),
+            Err("line 2: too many frames".to_string()));
+    }
+}
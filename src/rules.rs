@@ -0,0 +1,237 @@
+use std::str::FromStr;
+
+use crate::generator::GeneratorKind;
+use crate::gravity::{Gravity, GravityCurve};
+use crate::grid::{DEFAULT_HEIGHT, DEFAULT_HIDDEN_ROWS, DEFAULT_WIDTH};
+use crate::scoring::{ScoreTable, GUIDELINE_SCORING};
+
+pub const MIN_PREVIEW: usize = 1;
+pub const MAX_PREVIEW: usize = 6;
+
+// The narrowest and shortest boards every piece still fits in
+pub const MIN_WIDTH: usize = 4;
+pub const MIN_HEIGHT: usize = 4;
+pub const MAX_WIDTH: usize = 40;
+pub const MAX_HEIGHT: usize = 80;
+pub const MAX_HIDDEN_ROWS: usize = 40;
+
+// Command line flags that change the ruleset, for the game and the simulator
+pub const VALUE_FLAGS: [&str; 10] = [
+    "--width", "--height", "--hidden-rows", "--preview", "--gravity", "--lines-per-level", "--lock-delay",
+    "--lock-resets", "--das", "--arr",
+];
+pub const SWITCH_FLAGS: [&str; 2] = ["--no-das-carry", "--all-spin"];
+
+// The knobs that change how a game plays out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ruleset {
+    // Size of the visible playfield in cells
+    pub width: usize,
+    pub height: usize,
+    // Rows above the visible ones where pieces spawn, out of sight
+    pub hidden_rows: usize,
+    // How many upcoming pieces the player can see
+    pub preview: usize,
+    // What clears, combos and drops are worth
+    pub scoring: ScoreTable,
+    // The level goes up every this many cleared lines
+    pub lines_per_level: usize,
+    // How fast pieces fall at each level
+    pub gravity: GravityCurve,
+    // Frames a landed piece waits before locking, 30 is half a second
+    pub lock_delay: u32,
+    // How many moves or rotations on the ground restart the lock delay
+    // before the piece locks anyway
+    pub lock_resets: u32,
+    // Frames a direction has to be held before it starts repeating
+    pub das: u32,
+    // Frames between repeated moves, 0 goes straight to the wall
+    pub arr: u32,
+    // Whether a charged DAS keeps its charge when the next piece spawns
+    pub das_carry: bool,
+    // Whether pieces other than the T can spin, by rotating into a spot they
+    // can't move out of
+    pub all_spin: bool,
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Ruleset {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            hidden_rows: DEFAULT_HIDDEN_ROWS,
+            preview: 5,
+            scoring: GUIDELINE_SCORING,
+            lines_per_level: 10,
+            gravity: GravityCurve::Guideline,
+            lock_delay: 30,
+            lock_resets: 15,
+            das: 10,
+            arr: 2,
+            das_carry: true,
+            all_spin: false,
+        }
+    }
+}
+
+impl Ruleset {
+    // Applies one of the flags above, switches ignore the value. Values out
+    // of range are refused rather than clamped.
+    pub fn apply_flag(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        match flag {
+            "--width" => {
+                self.width = match value.parse() {
+                    Ok(n) if (MIN_WIDTH..=MAX_WIDTH).contains(&n) => n,
+                    _ => return Err(format!("invalid width '{}', expected {} to {}", value, MIN_WIDTH, MAX_WIDTH)),
+                };
+            },
+            "--height" => {
+                self.height = match value.parse() {
+                    Ok(n) if (MIN_HEIGHT..=MAX_HEIGHT).contains(&n) => n,
+                    _ => return Err(format!("invalid height '{}', expected {} to {}", value, MIN_HEIGHT, MAX_HEIGHT)),
+                };
+            },
+            "--hidden-rows" => {
+                self.hidden_rows = match value.parse() {
+                    Ok(n) if n <= MAX_HIDDEN_ROWS => n,
+                    _ => return Err(format!("invalid hidden rows '{}', expected 0 to {}", value, MAX_HIDDEN_ROWS)),
+                };
+            },
+            "--preview" => {
+                self.preview = match value.parse() {
+                    Ok(n) if (MIN_PREVIEW..=MAX_PREVIEW).contains(&n) => n,
+                    _ => return Err(format!("invalid preview '{}', expected {} to {}",
+                        value, MIN_PREVIEW, MAX_PREVIEW)),
+                };
+            },
+            "--gravity" => self.gravity = value.parse()?,
+            "--lines-per-level" => {
+                self.lines_per_level = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid lines per level '{}'", value)),
+                };
+            },
+            "--lock-delay" => {
+                self.lock_delay = value.parse()
+                    .map_err(|_| format!("invalid lock delay '{}', expected frames", value))?;
+            },
+            "--lock-resets" => {
+                self.lock_resets = value.parse()
+                    .map_err(|_| format!("invalid lock resets '{}', expected a number", value))?;
+            },
+            "--das" => {
+                self.das = value.parse()
+                    .map_err(|_| format!("invalid DAS '{}', expected frames", value))?;
+            },
+            "--arr" => {
+                self.arr = value.parse()
+                    .map_err(|_| format!("invalid ARR '{}', expected frames", value))?;
+            },
+            "--no-das-carry" => self.das_carry = false,
+            "--all-spin" => self.all_spin = true,
+            _ => return Err(format!("unknown argument '{}'", flag)),
+        }
+        Ok(())
+    }
+}
+
+// Ready made rulesets to pick from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    // Guideline rules, speeding up every ten lines
+    Marathon,
+    // NES speeds, a single preview, plain random pieces and no lock delay
+    Classic,
+    // 20G from the start with a generous lock delay
+    Master,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Marathon
+    }
+}
+
+impl Mode {
+    pub const ALL: [Mode; 3] = [Mode::Marathon, Mode::Classic, Mode::Master];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Marathon => "marathon",
+            Mode::Classic => "classic",
+            Mode::Master => "master",
+        }
+    }
+
+    pub fn ruleset(self) -> Ruleset {
+        match self {
+            Mode::Marathon => Ruleset::default(),
+            Mode::Classic => Ruleset {
+                preview: 1,
+                gravity: GravityCurve::Nes,
+                lock_delay: 0,
+                ..Ruleset::default()
+            },
+            Mode::Master => Ruleset {
+                preview: 3,
+                gravity: GravityCurve::Custom(vec![Gravity::TWENTY_G]),
+                lock_delay: 30,
+                ..Ruleset::default()
+            },
+        }
+    }
+
+    pub fn generator(self) -> GeneratorKind {
+        match self {
+            Mode::Marathon => GeneratorKind::Bag,
+            Mode::Classic => GeneratorKind::Uniform,
+            Mode::Master => GeneratorKind::History,
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Mode::ALL.iter().copied().find(|mode| mode.name() == name)
+            .ok_or_else(|| format!("unknown mode '{}', expected marathon, classic or master", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_names_round_trip() {
+        for &mode in Mode::ALL.iter() {
+            assert_eq!(mode.name().parse(), Ok(mode));
+        }
+        assert!("zen".parse::<Mode>().is_err());
+    }
+
+    #[test]
+    fn modes_play_differently() {
+        assert_eq!(Mode::Marathon.ruleset(), Ruleset::default());
+        assert_eq!(Mode::Classic.ruleset().gravity, GravityCurve::Nes);
+        assert_eq!(Mode::Master.ruleset().gravity.gravity(1), Gravity::TWENTY_G);
+    }
+
+    #[test]
+    fn flags_change_the_rules() {
+        let mut rules = Ruleset::default();
+        rules.apply_flag("--width", "12").unwrap();
+        rules.apply_flag("--gravity", "nes").unwrap();
+        rules.apply_flag("--lock-resets", "0").unwrap();
+        rules.apply_flag("--all-spin", "").unwrap();
+        assert_eq!(rules, Ruleset {
+            width: 12, gravity: GravityCurve::Nes, lock_resets: 0, all_spin: true, ..Ruleset::default()
+        });
+
+        assert_eq!(rules.apply_flag("--width", "41"), Err("invalid width '41', expected 4 to 40".to_string()));
+        assert_eq!(rules.apply_flag("--das", "soon"), Err("invalid DAS 'soon', expected frames".to_string()));
+        assert!(rules.apply_flag("--speed", "1").is_err());
+        assert_eq!(rules.width, 12);
+    }
+}
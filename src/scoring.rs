@@ -0,0 +1,341 @@
+// Guideline style scoring. What each clear is worth lives in a ScoreTable so
+// rulesets can swap the numbers without touching the logic below.
+
+use crate::spin::SpinKind;
+
+// Everything a lock can score for. T-spins score even when they clear nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClearKind {
+    Single,
+    Double,
+    Triple,
+    Tetris,
+    MiniTSpin,
+    MiniTSpinSingle,
+    MiniTSpinDouble,
+    TSpin,
+    TSpinSingle,
+    TSpinDouble,
+    TSpinTriple,
+}
+
+impl ClearKind {
+    pub fn from_lines(lines: usize) -> Option<ClearKind> {
+        match lines {
+            1 => Some(ClearKind::Single),
+            2 => Some(ClearKind::Double),
+            3 => Some(ClearKind::Triple),
+            4 => Some(ClearKind::Tetris),
+            _ => None,
+        }
+    }
+
+    // What a lock clearing this many lines is worth, given the T-spin it made
+    pub fn from_lock(lines: usize, t_spin: Option<SpinKind>) -> Option<ClearKind> {
+        match (t_spin, lines) {
+            (Some(SpinKind::Mini), 0) => Some(ClearKind::MiniTSpin),
+            (Some(SpinKind::Mini), 1) => Some(ClearKind::MiniTSpinSingle),
+            (Some(SpinKind::Mini), 2) => Some(ClearKind::MiniTSpinDouble),
+            (Some(SpinKind::Full), 0) => Some(ClearKind::TSpin),
+            (Some(SpinKind::Full), 1) => Some(ClearKind::TSpinSingle),
+            (Some(SpinKind::Full), 2) => Some(ClearKind::TSpinDouble),
+            (Some(SpinKind::Full), 3) => Some(ClearKind::TSpinTriple),
+            _ => ClearKind::from_lines(lines),
+        }
+    }
+
+    pub fn lines(self) -> usize {
+        match self {
+            ClearKind::MiniTSpin | ClearKind::TSpin => 0,
+            ClearKind::Single | ClearKind::MiniTSpinSingle | ClearKind::TSpinSingle => 1,
+            ClearKind::Double | ClearKind::MiniTSpinDouble | ClearKind::TSpinDouble => 2,
+            ClearKind::Triple | ClearKind::TSpinTriple => 3,
+            ClearKind::Tetris => 4,
+        }
+    }
+}
+
+// One row of the score table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClearScore {
+    pub kind: ClearKind,
+    // Multiplied by the level
+    pub points: u64,
+    // Difficult clears in a row earn the back-to-back bonus
+    pub difficult: bool,
+    pub label: &'static str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScoreTable {
+    pub clears: &'static [ClearScore],
+    // Multiplied by the combo count and the level
+    pub combo: u64,
+    // Per cell dropped
+    pub soft_drop: u64,
+    pub hard_drop: u64,
+    // A back-to-back clear is worth this percentage of its base points
+    pub back_to_back_percent: u64,
+    // Bonus for emptying the board, by the number of lines that did it
+    pub perfect_clear: [u64; 4],
+}
+
+pub const GUIDELINE_CLEARS: [ClearScore; 11] = [
+    ClearScore { kind: ClearKind::Single, points: 100, difficult: false, label: "Single" },
+    ClearScore { kind: ClearKind::Double, points: 300, difficult: false, label: "Double" },
+    ClearScore { kind: ClearKind::Triple, points: 500, difficult: false, label: "Triple" },
+    ClearScore { kind: ClearKind::Tetris, points: 800, difficult: true, label: "Tetris" },
+    ClearScore { kind: ClearKind::MiniTSpin, points: 100, difficult: false, label: "Mini T-Spin" },
+    ClearScore { kind: ClearKind::MiniTSpinSingle, points: 200, difficult: true, label: "Mini T-Spin Single" },
+    ClearScore { kind: ClearKind::MiniTSpinDouble, points: 400, difficult: true, label: "Mini T-Spin Double" },
+    ClearScore { kind: ClearKind::TSpin, points: 400, difficult: false, label: "T-Spin" },
+    ClearScore { kind: ClearKind::TSpinSingle, points: 800, difficult: true, label: "T-Spin Single" },
+    ClearScore { kind: ClearKind::TSpinDouble, points: 1200, difficult: true, label: "T-Spin Double" },
+    ClearScore { kind: ClearKind::TSpinTriple, points: 1600, difficult: true, label: "T-Spin Triple" },
+];
+
+pub const GUIDELINE_SCORING: ScoreTable = ScoreTable {
+    clears: &GUIDELINE_CLEARS,
+    combo: 50,
+    soft_drop: 1,
+    hard_drop: 2,
+    back_to_back_percent: 150,
+    perfect_clear: [800, 1200, 1800, 2000],
+};
+
+impl ScoreTable {
+    fn clear(&self, kind: ClearKind) -> Option<&ClearScore> {
+        self.clears.iter().find(|clear| clear.kind == kind)
+    }
+}
+
+// What the most recent clear was, for announcing it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LastClear {
+    pub label: &'static str,
+    pub back_to_back: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scoring {
+    table: ScoreTable,
+    score: u64,
+    // Consecutive clearing locks minus one, None once a lock clears nothing
+    combo: Option<u32>,
+    // Whether the last clear was a difficult one
+    back_to_back: bool,
+    last_clear: Option<LastClear>,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Scoring::new(GUIDELINE_SCORING)
+    }
+}
+
+impl Scoring {
+    pub fn new(table: ScoreTable) -> Scoring {
+        Scoring {
+            table,
+            score: 0,
+            combo: None,
+            back_to_back: false,
+            last_clear: None,
+        }
+    }
+
+    pub fn score(&self) -> u64 {
+        self.score
+    }
+
+    pub fn combo(&self) -> Option<u32> {
+        self.combo
+    }
+
+    pub fn back_to_back(&self) -> bool {
+        self.back_to_back
+    }
+
+    pub fn last_clear(&self) -> Option<LastClear> {
+        self.last_clear
+    }
+
+    pub fn soft_drop(&mut self, cells: u32) {
+        self.score += cells as u64 * self.table.soft_drop;
+    }
+
+    pub fn hard_drop(&mut self, cells: u32) {
+        self.score += cells as u64 * self.table.hard_drop;
+    }
+
+    // Adds the bonus for a clear that left the board empty and returns it.
+    // Scored on top of the clear itself.
+    pub fn perfect_clear(&mut self, lines: usize, level: u32) -> u64 {
+        let points = match lines {
+            1..=4 => self.table.perfect_clear[lines - 1] * level as u64,
+            _ => 0,
+        };
+        self.score += points;
+        points
+    }
+
+    // Scores a piece locking with the given clear, or none, and returns the points
+    pub fn lock(&mut self, clear: Option<ClearKind>, level: u32) -> u64 {
+        let level = level as u64;
+        let clear = match clear.and_then(|kind| self.table.clear(kind)) {
+            Some(clear) => *clear,
+            None => {
+                self.combo = None;
+                return 0;
+            },
+        };
+
+        // A spin that clears nothing ends the combo but leaves back-to-back be
+        if clear.kind.lines() == 0 {
+            let points = clear.points * level;
+            self.combo = None;
+            self.last_clear = Some(LastClear { label: clear.label, back_to_back: false });
+            self.score += points;
+            return points;
+        }
+
+        let back_to_back = clear.difficult && self.back_to_back;
+        let mut points = clear.points * level;
+        if back_to_back {
+            points = points * self.table.back_to_back_percent / 100;
+        }
+
+        let combo = self.combo.map_or(0, |combo| combo + 1);
+        points += self.table.combo * combo as u64 * level;
+
+        self.combo = Some(combo);
+        self.back_to_back = clear.difficult;
+        self.last_clear = Some(LastClear { label: clear.label, back_to_back });
+        self.score += points;
+        points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_clears_scale_with_level() {
+        let expected = [(1, 100), (2, 300), (3, 500), (4, 800)];
+        for &(lines, points) in expected.iter() {
+            for level in 1..4 {
+                let mut scoring = Scoring::default();
+                assert_eq!(scoring.lock(ClearKind::from_lines(lines), level), points * level as u64);
+                assert_eq!(scoring.score(), points * level as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn locks_without_clears_score_nothing() {
+        let mut scoring = Scoring::default();
+        assert_eq!(scoring.lock(ClearKind::from_lines(0), 5), 0);
+        assert_eq!(scoring.score(), 0);
+        assert_eq!(scoring.last_clear(), None);
+    }
+
+    #[test]
+    fn drops_score_per_cell() {
+        let mut scoring = Scoring::default();
+        scoring.soft_drop(7);
+        scoring.hard_drop(10);
+        assert_eq!(scoring.score(), 7 + 20);
+    }
+
+    #[test]
+    fn combo_grows_with_consecutive_clears() {
+        let mut scoring = Scoring::default();
+
+        assert_eq!(scoring.lock(Some(ClearKind::Single), 1), 100);
+        assert_eq!(scoring.combo(), Some(0));
+        assert_eq!(scoring.lock(Some(ClearKind::Single), 1), 100 + 50);
+        assert_eq!(scoring.lock(Some(ClearKind::Double), 2), 600 + 2 * 50 * 2);
+        assert_eq!(scoring.combo(), Some(2));
+    }
+
+    #[test]
+    fn combo_ends_on_a_lock_without_clear() {
+        let mut scoring = Scoring::default();
+        scoring.lock(Some(ClearKind::Single), 1);
+        scoring.lock(Some(ClearKind::Single), 1);
+
+        scoring.lock(None, 1);
+        assert_eq!(scoring.combo(), None);
+        assert_eq!(scoring.lock(Some(ClearKind::Single), 1), 100);
+    }
+
+    #[test]
+    fn back_to_back_tetrises_earn_the_bonus() {
+        let mut scoring = Scoring::default();
+
+        assert_eq!(scoring.lock(Some(ClearKind::Tetris), 1), 800);
+        assert!(!scoring.last_clear().unwrap().back_to_back);
+        scoring.lock(None, 1);
+
+        assert_eq!(scoring.lock(Some(ClearKind::Tetris), 1), 1200);
+        assert_eq!(scoring.last_clear(), Some(LastClear { label: "Tetris", back_to_back: true }));
+    }
+
+    #[test]
+    fn easy_clears_break_back_to_back() {
+        let mut scoring = Scoring::default();
+        scoring.lock(Some(ClearKind::Tetris), 1);
+        scoring.lock(None, 1);
+        scoring.lock(Some(ClearKind::Triple), 1);
+        scoring.lock(None, 1);
+
+        assert!(!scoring.back_to_back());
+        assert_eq!(scoring.lock(Some(ClearKind::Tetris), 1), 800);
+    }
+
+    #[test]
+    fn custom_tables_change_the_points() {
+        const CLEARS: [ClearScore; 1] = [
+            ClearScore { kind: ClearKind::Single, points: 40, difficult: true, label: "One" },
+        ];
+        let table = ScoreTable { clears: &CLEARS, combo: 0, back_to_back_percent: 200, ..GUIDELINE_SCORING };
+        let mut scoring = Scoring::new(table);
+
+        assert_eq!(scoring.lock(Some(ClearKind::Single), 1), 40);
+        assert_eq!(scoring.lock(Some(ClearKind::Single), 1), 80);
+        assert_eq!(scoring.lock(Some(ClearKind::Tetris), 1), 0);
+        assert_eq!(scoring.score(), 120);
+    }
+
+    #[test]
+    fn t_spins_score_with_or_without_lines() {
+        let mut scoring = Scoring::default();
+        assert_eq!(scoring.lock(ClearKind::from_lock(0, Some(SpinKind::Full)), 2), 800);
+        assert_eq!(scoring.combo(), None);
+        assert_eq!(scoring.last_clear().unwrap().label, "T-Spin");
+
+        assert_eq!(scoring.lock(ClearKind::from_lock(2, Some(SpinKind::Full)), 1), 1200);
+        assert_eq!(scoring.lock(ClearKind::from_lock(1, Some(SpinKind::Mini)), 1), 300 + 50);
+        assert_eq!(scoring.last_clear(), Some(LastClear { label: "Mini T-Spin Single", back_to_back: true }));
+    }
+
+    #[test]
+    fn spins_without_lines_keep_back_to_back() {
+        let mut scoring = Scoring::default();
+        scoring.lock(Some(ClearKind::Tetris), 1);
+        scoring.lock(ClearKind::from_lock(0, Some(SpinKind::Mini)), 1);
+
+        assert!(scoring.back_to_back());
+        assert_eq!(scoring.lock(ClearKind::from_lock(3, Some(SpinKind::Full)), 1), 2400);
+    }
+
+    #[test]
+    fn perfect_clears_earn_a_bonus_by_lines() {
+        let mut scoring = Scoring::default();
+        assert_eq!(scoring.perfect_clear(4, 1), 2000);
+        assert_eq!(scoring.perfect_clear(1, 3), 2400);
+        assert_eq!(scoring.perfect_clear(0, 1), 0);
+        assert_eq!(scoring.score(), 4400);
+    }
+}
@@ -0,0 +1,152 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+}
+
+// Delayed auto shift. Holding a direction moves the piece once right away,
+// then after `das` frames it keeps moving every `arr` frames. The charge
+// only depends on how long the key has been held, so it carries over to the
+// next piece unless the game discharges it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AutoShift {
+    left: bool,
+    right: bool,
+    // The most recently pressed direction that is still held
+    active: Option<Direction>,
+    // Frames the active direction has been held
+    charge: u32,
+}
+
+impl AutoShift {
+    pub fn press(&mut self, direction: Direction) {
+        match direction {
+            Direction::Left => self.left = true,
+            Direction::Right => self.right = true,
+        }
+        self.active = Some(direction);
+        self.charge = 0;
+    }
+
+    // Letting go of the active direction falls back to the other one if it's
+    // still held, charging from scratch
+    pub fn release(&mut self, direction: Direction) {
+        match direction {
+            Direction::Left => self.left = false,
+            Direction::Right => self.right = false,
+        }
+
+        if self.active == Some(direction) {
+            self.active = match direction {
+                Direction::Left if self.right => Some(Direction::Right),
+                Direction::Right if self.left => Some(Direction::Left),
+                _ => None,
+            };
+            self.charge = 0;
+        }
+    }
+
+    pub fn direction(&self) -> Option<Direction> {
+        self.active
+    }
+
+    pub fn is_charged(&self, das: u32) -> bool {
+        self.active.is_some() && self.charge >= das
+    }
+
+    pub fn discharge(&mut self) {
+        self.charge = 0;
+    }
+
+    // Advances one frame and returns how many cells to shift and which way.
+    // An ARR of 0 asks for as many cells as it takes to reach the wall.
+    pub fn tick(&mut self, das: u32, arr: u32) -> Option<(Direction, u32)> {
+        let direction = self.active?;
+        self.charge += 1;
+        if self.charge < das {
+            return None;
+        }
+
+        if arr == 0 {
+            return Some((direction, u32::MAX));
+        }
+
+        let repeats = self.charge - das;
+        if repeats >= arr {
+            self.charge -= arr;
+        }
+        if repeats % arr == 0 {
+            Some((direction, 1))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Which frames out of the first `frames` shift
+    fn shifting_frames(shift: &mut AutoShift, frames: u32, das: u32, arr: u32) -> Vec<u32> {
+        (1..=frames).filter(|_| shift.tick(das, arr).is_some()).collect()
+    }
+
+    #[test]
+    fn repeats_after_the_delay_at_the_repeat_rate() {
+        let mut shift = AutoShift::default();
+        shift.press(Direction::Right);
+
+        assert_eq!(shifting_frames(&mut shift, 16, 10, 2), vec![10, 12, 14, 16]);
+        assert!(shift.is_charged(10));
+    }
+
+    #[test]
+    fn zero_repeat_rate_goes_straight_to_the_wall() {
+        let mut shift = AutoShift::default();
+        shift.press(Direction::Left);
+
+        for _ in 1..5 {
+            assert_eq!(shift.tick(5, 0), None);
+        }
+        assert_eq!(shift.tick(5, 0), Some((Direction::Left, u32::MAX)));
+    }
+
+    #[test]
+    fn nothing_happens_without_a_held_direction() {
+        let mut shift = AutoShift::default();
+        assert_eq!(shifting_frames(&mut shift, 30, 10, 2), vec![]);
+
+        shift.press(Direction::Left);
+        shift.release(Direction::Left);
+        assert_eq!(shifting_frames(&mut shift, 30, 10, 2), vec![]);
+    }
+
+    #[test]
+    fn latest_direction_wins_and_falls_back_when_released() {
+        let mut shift = AutoShift::default();
+        shift.press(Direction::Left);
+        shifting_frames(&mut shift, 12, 10, 2);
+
+        shift.press(Direction::Right);
+        assert_eq!(shift.direction(), Some(Direction::Right));
+        assert!(!shift.is_charged(10));
+
+        shift.release(Direction::Right);
+        assert_eq!(shift.direction(), Some(Direction::Left));
+        assert_eq!(shifting_frames(&mut shift, 10, 10, 2), vec![10]);
+    }
+
+    #[test]
+    fn releasing_the_other_direction_keeps_the_charge() {
+        let mut shift = AutoShift::default();
+        shift.press(Direction::Left);
+        shift.press(Direction::Right);
+        shifting_frames(&mut shift, 10, 10, 2);
+
+        shift.release(Direction::Left);
+        assert!(shift.is_charged(10));
+        assert_eq!(shift.tick(10, 2), None);
+        assert_eq!(shift.tick(10, 2), Some((Direction::Right, 1)));
+    }
+}
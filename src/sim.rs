@@ -0,0 +1,126 @@
+// Games played without a window, for rustycubes-sim. Whoever plays, the game
+// runs until it's over or enough pieces are down, and ends in a summary.
+
+use crate::bot;
+use crate::game::{Game, GamePhase};
+use crate::gamepad::ActionEvent;
+use crate::grid::Grid;
+use crate::replay::{self, Playback, Replay};
+
+// Bots and scripts never give up on their own, so they stop here unless told
+// otherwise
+pub const DEFAULT_PIECES: usize = 1000;
+
+// Who's playing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Player {
+    Bot,
+    // The replay's inputs, then nothing once they run out
+    Script,
+    // A recorded game, which should end exactly where the recording did
+    Replay,
+}
+
+// Plays until the game ends or `pieces` are down, a replay only until its
+// inputs run out. A replay that ends up somewhere other than its recording
+// is an error.
+pub fn run(player: Player, replay: Replay, pieces: Option<usize>, game: &mut Game) -> Result<(), String> {
+    let pieces = match player {
+        Player::Replay => pieces.unwrap_or(usize::MAX),
+        _ => pieces.unwrap_or(DEFAULT_PIECES),
+    };
+
+    match player {
+        // One piece a frame, everything it takes to place it pressed at once
+        Player::Bot => {
+            while game.phase() == GamePhase::Playing && game.pieces() < pieces {
+                for action in bot::plan(game).unwrap_or_default() {
+                    replay::apply(game, ActionEvent::Press(action));
+                    replay::apply(game, ActionEvent::Release(action));
+                }
+                game.tick();
+            }
+            Ok(())
+        },
+        Player::Script => {
+            let mut playback = Playback::new(replay);
+            while game.pieces() < pieces && playback.step(game) {}
+
+            // Gravity keeps going once the script runs out
+            while game.phase() == GamePhase::Playing && game.pieces() < pieces {
+                game.tick();
+            }
+            Ok(())
+        },
+        Player::Replay => {
+            let mut playback = Playback::new(replay);
+            while game.pieces() < pieces && playback.step(game) {}
+
+            if game.pieces() < pieces {
+                playback.check(game)
+            } else {
+                Ok(())
+            }
+        },
+    }
+}
+
+// One line per row, each cell the letter of the piece it came from. Hidden
+// rows only show up once there's something in them.
+pub fn board_rows(grid: &Grid) -> Vec<String> {
+    let width = grid.width() as i16;
+    let top = (grid.top()..0).find(|&y| (0..width).any(|x| grid.is_occupied((x, y).into()))).unwrap_or(0);
+
+    (top..grid.height() as i16).map(|y| {
+        (0..width).map(|x| match grid.get((x, y).into()) {
+            Some(kind) => format!("{:?}", kind),
+            None => ".".to_string(),
+        }).collect()
+    }).collect()
+}
+
+pub fn text_summary(seed: u64, game: &Game) -> String {
+    let mut lines = vec![
+        format!("seed: {}", seed),
+        format!("score: {}", game.score()),
+        format!("lines: {}", game.lines()),
+        format!("pieces: {}", game.pieces()),
+        format!("level: {}", game.level()),
+        format!("frames: {}", game.frame()),
+        format!("game over: {}", game.phase() == GamePhase::GameOver),
+        String::new(),
+    ];
+    lines.extend(board_rows(game.grid()));
+    lines.join("\n")
+}
+
+// Nothing in the output needs escaping, so it's written out by hand
+pub fn json_summary(seed: u64, game: &Game) -> String {
+    let board: Vec<String> = board_rows(game.grid()).iter().map(|row| format!("\"{}\"", row)).collect();
+    format!("{{\"seed\":{},\"score\":{},\"lines\":{},\"pieces\":{},\"level\":{},\"frames\":{},\"game_over\":{},\"board\":[{}]}}",
+        seed, game.score(), game.lines(), game.pieces(), game.level(), game.frame(),
+        game.phase() == GamePhase::GameOver, board.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::GeneratorKind;
+    use crate::rules::Ruleset;
+
+    #[test]
+    fn scripts_play_on_after_their_last_input() {
+        let rules = Ruleset { width: 4, ..Ruleset::default() };
+        let mut replay = Replay::new(rules, GeneratorKind::Bag, 1);
+        replay.events = replay::parse_events("# drop the first piece and leave the rest to gravity\n0 +hard_drop\n").unwrap();
+
+        let mut game = replay.start();
+        run(Player::Script, replay, Some(4), &mut game).unwrap();
+
+        // The hard drop alone clears nothing, the pieces after it finish a line
+        assert_eq!(game.pieces(), 4);
+        assert_eq!(game.lines(), 1);
+        assert!(text_summary(1, &game).contains("\nlines: 1\n"));
+        assert!(json_summary(1, &game).contains("\"lines\":1,"));
+    }
+}
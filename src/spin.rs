@@ -0,0 +1,263 @@
+use crate::grid::Grid;
+use crate::piece::{Piece, PieceKind};
+
+// What the piece last did that worked. Spins only count when the piece
+// locks straight after a rotation, falling or shifting afterwards undoes it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LastAction {
+    // Nothing yet, the piece just spawned
+    None,
+    Move,
+    // Rotated using the given kick from the SRS tables, 0 is in place
+    Rotate { kick: usize },
+}
+
+impl Default for LastAction {
+    fn default() -> Self {
+        LastAction::None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpinKind {
+    Mini,
+    Full,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Spin {
+    pub piece: PieceKind,
+    pub kind: SpinKind,
+}
+
+// The last kick in the tables. A T that needed it to get in, like in a TST
+// or a fin, always gets a full T-spin.
+const UPGRADE_KICK: usize = 4;
+
+// The corners of the T's 3x3 box
+const T_CORNERS: [(i16, i16); 4] = [(0, 0), (2, 0), (0, 2), (2, 2)];
+
+// The two corners on either side of the T's nub, for each rotation
+const T_FRONT_CORNERS: [[(i16, i16); 2]; 4] = [
+    [(0, 0), (2, 0)],
+    [(2, 0), (2, 2)],
+    [(0, 2), (2, 2)],
+    [(0, 0), (0, 2)],
+];
+
+// The 3-corner rule: a T locking right after a rotation with at least three
+// corners of its box blocked is a T-spin. It's a full one when both corners
+// in front are blocked, otherwise a mini unless the last kick got it there.
+pub fn t_spin(piece: &Piece, grid: &Grid, last_action: LastAction) -> Option<SpinKind> {
+    let kick = match last_action {
+        LastAction::Rotate { kick } if piece.kind() == PieceKind::T => kick,
+        _ => return None,
+    };
+
+    let position = piece.position();
+    let solid = |&(x, y): &(i16, i16)| grid.is_solid((position.x + x, position.y + y).into());
+    if T_CORNERS.iter().filter(|corner| solid(corner)).count() < 3 {
+        return None;
+    }
+
+    if kick == UPGRADE_KICK || T_FRONT_CORNERS[piece.rotation() as usize].iter().all(solid) {
+        Some(SpinKind::Full)
+    } else {
+        Some(SpinKind::Mini)
+    }
+}
+
+// Whether the piece is stuck where it is, unable to go left, right or up
+pub fn is_immobile(piece: &Piece, grid: &Grid) -> bool {
+    let steps: [fn(&mut Piece); 3] = [Piece::move_left, Piece::move_right, Piece::move_up];
+    steps.iter().all(|step| {
+        let mut moved = piece.clone();
+        step(&mut moved);
+        !grid.fits(&moved)
+    })
+}
+
+// Whether the piece is spinning as it locks, and what kind of spin it is.
+// T pieces go by the corner rule. With all-spin on, any other piece except
+// the O counts as a full spin when it rotated into a spot it can't move out of.
+pub fn detect(piece: &Piece, grid: &Grid, last_action: LastAction, all_spin: bool) -> Option<Spin> {
+    let kind = match piece.kind() {
+        PieceKind::T => t_spin(piece, grid, last_action)?,
+        PieceKind::O => return None,
+        _ if all_spin && matches!(last_action, LastAction::Rotate { .. }) && is_immobile(piece, grid) =>
+            SpinKind::Full,
+        _ => return None,
+    };
+    Some(Spin { piece: piece.kind(), kind })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Puts a T in the given rotation at (x, y), as if it had just rotated there
+    fn t_at(x: i16, y: i16, rotation: i8) -> Piece {
+        let mut piece = Piece::new(x, y, PieceKind::T);
+        piece.set_rotation(rotation);
+        piece
+    }
+
+    fn rotated(kick: usize) -> LastAction {
+        LastAction::Rotate { kick }
+    }
+
+    #[test]
+    fn t_spin_double_slot() {
+        let grid = Grid::from_rows(&[
+            "..........",
+            "..........",
+            "...#......",
+            "###...####",
+            "####.#####",
+        ]);
+        let piece = t_at(3, 2, 2);
+        assert!(grid.fits(&piece));
+
+        assert_eq!(t_spin(&piece, &grid, rotated(0)), Some(SpinKind::Full));
+        assert_eq!(detect(&piece, &grid, rotated(0), false), Some(Spin { piece: PieceKind::T, kind: SpinKind::Full }));
+    }
+
+    #[test]
+    fn mini_when_a_front_corner_is_open() {
+        let grid = Grid::from_rows(&[
+            "..........",
+            "..........",
+            "#.........",
+            "...#######",
+        ]);
+        let piece = t_at(0, 2, 0);
+        assert!(grid.fits(&piece));
+
+        assert_eq!(t_spin(&piece, &grid, rotated(1)), Some(SpinKind::Mini));
+    }
+
+    #[test]
+    fn the_last_kick_upgrades_a_mini() {
+        let grid = Grid::from_rows(&[
+            "..........",
+            "..........",
+            "#.........",
+            "...#######",
+        ]);
+        assert_eq!(t_spin(&t_at(0, 2, 0), &grid, rotated(UPGRADE_KICK)), Some(SpinKind::Full));
+    }
+
+    #[test]
+    fn t_spin_triple_kicks_in() {
+        let grid = Grid::from_rows(&[
+            "..#.......",
+            "..........",
+            "##.#######",
+            "#..#######",
+            "##.#######",
+        ]);
+        let mut piece = t_at(0, 0, 0);
+        let kick = piece.turn(&grid, -1).unwrap();
+
+        assert_eq!(kick, UPGRADE_KICK);
+        assert_eq!(t_spin(&piece, &grid, rotated(kick)), Some(SpinKind::Full));
+    }
+
+    #[test]
+    fn no_spin_without_a_rotation_last() {
+        let grid = Grid::from_rows(&[
+            "..........",
+            "..........",
+            "...#......",
+            "###...####",
+            "####.#####",
+        ]);
+        let piece = t_at(3, 2, 2);
+
+        assert_eq!(t_spin(&piece, &grid, LastAction::Move), None);
+        assert_eq!(t_spin(&piece, &grid, LastAction::None), None);
+    }
+
+    #[test]
+    fn two_corners_are_not_enough() {
+        let grid = Grid::from_rows(&[
+            "..........",
+            "..........",
+            "..........",
+            "###...####",
+            "####.#####",
+        ]);
+        assert_eq!(t_spin(&t_at(3, 2, 2), &grid, rotated(0)), None);
+
+        // Only T pieces count for the corner rule
+        let mut piece = Piece::new(3, 2, PieceKind::L);
+        piece.set_rotation(2);
+        assert_eq!(t_spin(&piece, &grid, rotated(0)), None);
+    }
+
+    #[test]
+    fn all_spin_needs_the_piece_stuck() {
+        // An S tucked under an overhang it can't get back out of
+        let grid = Grid::from_rows(&[
+            "..........",
+            "#####.####",
+            "##..######",
+            "#..#######",
+        ]);
+        let piece = Piece::new(1, 2, PieceKind::S);
+        assert!(grid.fits(&piece));
+        assert!(is_immobile(&piece, &grid));
+
+        let spin = Some(Spin { piece: PieceKind::S, kind: SpinKind::Full });
+        assert_eq!(detect(&piece, &grid, rotated(2), true), spin);
+        assert_eq!(detect(&piece, &grid, rotated(2), false), None);
+        assert_eq!(detect(&piece, &grid, LastAction::Move, true), None);
+
+        // The same S out in the open can just slide away
+        let open = Grid::from_rows(&["..........", "..........", "..........", ".........."]);
+        assert!(!is_immobile(&piece, &open));
+        assert_eq!(detect(&piece, &open, rotated(0), true), None);
+    }
+
+    #[test]
+    fn all_spin_covers_every_piece_but_o() {
+        let grid = Grid::from_rows(&[
+            "##########",
+            "##..######",
+            "##..######",
+            "##########",
+        ]);
+        let o = Piece::new(2, 1, PieceKind::O);
+        assert!(grid.fits(&o) && is_immobile(&o, &grid));
+        assert_eq!(detect(&o, &grid, rotated(0), true), None);
+
+        // Every other piece spins when it's boxed in on all sides by a hole
+        // exactly its shape, and not when it's out in the open
+        let solid = ["######"; 6];
+        for &kind in [PieceKind::S, PieceKind::Z, PieceKind::J, PieceKind::L, PieceKind::I].iter() {
+            let piece = Piece::new(1, 1, kind);
+            let mut hole = Grid::from_rows(&solid);
+            for &pos in piece.cells().iter() {
+                hole.set(pos, None);
+            }
+            assert!(hole.fits(&piece) && is_immobile(&piece, &hole), "{:?}", kind);
+            assert_eq!(detect(&piece, &hole, rotated(0), true), Some(Spin { piece: kind, kind: SpinKind::Full }));
+            assert_eq!(detect(&piece, &hole, rotated(0), false), None);
+
+            let open = Grid::from_rows(&["......"; 6]);
+            assert_eq!(detect(&piece, &open, rotated(0), true), None, "{:?}", kind);
+        }
+
+        // An I standing in a well can still slide up and out, unless it's capped
+        let mut i = Piece::new(-1, 0, PieceKind::I);
+        i.set_rotation(1);
+        let well = Grid::from_rows(&["#.#", "#.#", "#.#", "#.#"]);
+        assert!(well.fits(&i));
+        assert_eq!(detect(&i, &well, rotated(0), true), None);
+
+        i.move_down();
+        let capped = Grid::from_rows(&["###", "#.#", "#.#", "#.#", "#.#"]);
+        assert!(capped.fits(&i));
+        assert_eq!(detect(&i, &capped, rotated(0), true), Some(Spin { piece: PieceKind::I, kind: SpinKind::Full }));
+    }
+}